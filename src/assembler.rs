@@ -0,0 +1,455 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use crate::{
+    cli::AssembleArgs,
+    cpu::{RAM_SIZE, ROM_ADDRESS},
+    symbols::Symbols,
+};
+
+// Assemble a source file to a ROM, and write the symbols next to it for the debuggers and the profiler.
+pub fn run(args: AssembleArgs) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(&args.source_path)
+        .map_err(|error| format!("Cannot read {}: {}", args.source_path.display(), error))?;
+    let (rom, mut symbols) =
+        assemble(&source).map_err(|error| format!("{}: {}", args.source_path.display(), error))?;
+    let output = args
+        .output
+        .unwrap_or_else(|| args.source_path.with_extension("ch8"));
+
+    // The source is referenced by its name when it's next to the ROM, so that both can be moved together
+    let directory = |path: &Path| {
+        fs::canonicalize(path)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+    };
+
+    fs::write(&output, &rom)
+        .map_err(|error| format!("Cannot write {}: {}", output.display(), error))?;
+
+    symbols.source = match (directory(&args.source_path), directory(&output)) {
+        (Some(source), Some(rom)) if source == rom => args.source_path.file_name().map(Into::into),
+        _ => fs::canonicalize(&args.source_path).ok(),
+    };
+    symbols.save(&output)?;
+    println!("Assembled {} bytes to {}", rom.len(), output.display());
+
+    Ok(())
+}
+
+// Assemble a program written with the mnemonics of Cowgod's Chip-8 technical reference, the ones shown by the
+// disassembler, e.g.:
+//
+//     main_loop:          ; A label is the address of what follows it
+//         LD V0, 0x05
+//         CALL draw_ball
+//         JP main_loop
+//     ball:
+//         DB 0x80, 0b10000000
+//
+// Labels can be used wherever an address or a value is expected. Numbers are given in decimal, in hexadecimal (`0x`)
+// or in binary (`0b`), and data with `DB` (bytes) or `DW` (16-bit words, big-endian).
+pub fn assemble(source: &str) -> Result<(Vec<u8>, Symbols), String> {
+    let mut labels = BTreeMap::new();
+    let mut statements = Vec::new();
+    let mut address = ROM_ADDRESS as usize;
+
+    // The labels can be used before being defined, so their addresses are found in a first pass
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index as u32 + 1;
+        let error = |message: String| format!("line {}: {}", line_number, message);
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+
+            if !is_label(label) {
+                return Err(error(format!("invalid label `{}`", label)));
+            }
+
+            if labels.insert(label.to_string(), address as u16).is_some() {
+                return Err(error(format!("label `{}` defined twice", label)));
+            }
+
+            text = rest.trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = split_statement(text);
+        let size = match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            _ => 2,
+        };
+
+        statements.push((line_number, mnemonic, operands));
+        address += size;
+
+        if address > RAM_SIZE {
+            return Err(error("the program doesn't fit in memory".to_string()));
+        }
+    }
+
+    let mut rom = Vec::new();
+    let mut lines = Vec::new();
+
+    for (line_number, mnemonic, operands) in statements {
+        let resolve = resolver(&labels, parse_number);
+        let error = |message: String| format!("line {}: {}", line_number, message);
+
+        lines.push((ROM_ADDRESS + rom.len() as u16, line_number));
+
+        match mnemonic.as_str() {
+            "DB" => {
+                for operand in operands {
+                    let value = resolve(operand).and_then(byte).map_err(error)?;
+
+                    rom.push(value as u8);
+                }
+            }
+            "DW" => {
+                for operand in operands {
+                    let value = resolve(operand).map_err(error)?;
+
+                    rom.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            _ => {
+                let instruction = encode(&mnemonic, &operands, &resolve).map_err(error)?;
+
+                rom.extend_from_slice(&instruction.to_be_bytes());
+            }
+        }
+    }
+
+    Ok((
+        rom,
+        Symbols {
+            source: None,
+            lines,
+            labels,
+        },
+    ))
+}
+
+// Assemble a single instruction, e.g. `LD V0, 0x05`, in which the given labels can be used. The numbers are read with
+// the given parser, so that the monitor can take them in hexadecimal as everywhere else.
+pub fn assemble_instruction(
+    text: &str,
+    labels: &BTreeMap<String, u16>,
+    parse_number: fn(&str) -> Option<u16>,
+) -> Result<u16, String> {
+    let (mnemonic, operands) = split_statement(text.trim());
+    let resolve = resolver(labels, parse_number);
+
+    encode(&mnemonic, &operands, &resolve)
+}
+
+// The operands of an instruction, besides numbers and labels.
+#[derive(Clone, Copy, PartialEq)]
+enum Operand {
+    V(u16),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Value(u16),
+}
+
+fn encode(
+    mnemonic: &str,
+    operands: &[&str],
+    resolve: &dyn Fn(&str) -> Result<u16, String>,
+) -> Result<u16, String> {
+    use Operand::*;
+
+    let parsed = operands
+        .iter()
+        .map(|operand| parse_operand(operand, resolve))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let instruction = match (mnemonic, parsed.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [Value(a)]) => address(*a)?,
+        ("JP", [Value(a)]) => 0x1000 | address(*a)?,
+
+        // On the SUPER-CHIP, BXNN jumps to XNN + VX, so the register is given by the address
+        ("JP", [V(x), Value(a)]) if *x == 0 || address(*a)? >> 8 == *x => 0xB000 | address(*a)?,
+        ("JP", [V(x), Value(_)]) => {
+            return Err(format!(
+                "JP V{:X} can only jump to an address starting with {:X}",
+                x, x
+            ))
+        }
+        ("CALL", [Value(a)]) => 0x2000 | address(*a)?,
+        ("SE", [V(x), Value(n)]) => 0x3000 | x << 8 | byte(*n)?,
+        ("SNE", [V(x), Value(n)]) => 0x4000 | x << 8 | byte(*n)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+        ("LD", [V(x), Value(n)]) => 0x6000 | x << 8 | byte(*n)?,
+        ("ADD", [V(x), Value(n)]) => 0x7000 | x << 8 | byte(*n)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+        ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+        ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+        ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SHR", [V(x)]) => 0x8006 | x << 8 | x << 4,
+        ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", [V(x), V(y)]) => 0x800E | x << 8 | y << 4,
+        ("SHL", [V(x)]) => 0x800E | x << 8 | x << 4,
+        ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", [I, Value(a)]) => 0xA000 | address(*a)?,
+        ("RND", [V(x), Value(n)]) => 0xC000 | x << 8 | byte(*n)?,
+        ("DRW", [V(x), V(y), Value(n)]) if *n <= 0xF => 0xD000 | x << 8 | y << 4 | n,
+        ("SKP", [V(x)]) => 0xE09E | x << 8,
+        ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+        ("LD", [V(x), DelayTimer]) => 0xF007 | x << 8,
+        ("LD", [V(x), Key]) => 0xF00A | x << 8,
+        ("LD", [DelayTimer, V(x)]) => 0xF015 | x << 8,
+        ("LD", [SoundTimer, V(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+        ("LD", [Font, V(x)]) => 0xF029 | x << 8,
+        ("LD", [Bcd, V(x)]) => 0xF033 | x << 8,
+        ("LD", [IndirectI, V(x)]) => 0xF055 | x << 8,
+        ("LD", [V(x), IndirectI]) => 0xF065 | x << 8,
+        _ => {
+            return Err(format!(
+                "invalid instruction `{} {}`",
+                mnemonic,
+                operands.join(", ")
+            ))
+        }
+    };
+
+    Ok(instruction)
+}
+
+// Registers and special operands are case-insensitive, and take precedence over labels with the same name.
+fn parse_operand(
+    operand: &str,
+    resolve: &dyn Fn(&str) -> Result<u16, String>,
+) -> Result<Operand, String> {
+    let operand = match operand.to_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        register if register.len() == 2 && register.starts_with('V') => {
+            match u16::from_str_radix(&register[1..], 16) {
+                Ok(x) => Operand::V(x),
+                Err(_) => Operand::Value(resolve(operand)?),
+            }
+        }
+        _ => Operand::Value(resolve(operand)?),
+    };
+
+    Ok(operand)
+}
+
+// Resolve an operand that is either a label or a number.
+fn resolver(
+    labels: &BTreeMap<String, u16>,
+    parse_number: fn(&str) -> Option<u16>,
+) -> impl Fn(&str) -> Result<u16, String> + '_ {
+    move |operand| match labels.get(operand) {
+        Some(address) => Ok(*address),
+        None => {
+            parse_number(operand).ok_or(format!("unknown label or invalid number `{}`", operand))
+        }
+    }
+}
+
+fn address(value: u16) -> Result<u16, String> {
+    match value {
+        0..=0xFFF => Ok(value),
+        _ => Err(format!("0x{:X} is not a 12-bit address", value)),
+    }
+}
+
+fn byte(value: u16) -> Result<u16, String> {
+    match value {
+        0..=0xFF => Ok(value),
+        _ => Err(format!("0x{:X} doesn't fit in a byte", value)),
+    }
+}
+
+// Split a statement in its mnemonic (in uppercase) and its operands, separated by commas.
+fn split_statement(text: &str) -> (String, Vec<&str>) {
+    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let operands = match operands.trim() {
+        "" => Vec::new(),
+        operands => operands.split(',').map(str::trim).collect(),
+    };
+
+    (mnemonic.to_uppercase(), operands)
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    let lowercase = text.to_lowercase();
+
+    if let Some(hexadecimal) = lowercase.strip_prefix("0x") {
+        u16::from_str_radix(hexadecimal, 16).ok()
+    } else if let Some(binary) = lowercase.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn is_label(text: &str) -> bool {
+    let mut characters = text.chars();
+
+    characters
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && characters.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::operation::Operation;
+
+    const PROGRAM: &str = "; test program
+start:
+    LD V0, 0x05     ; labels can be used before being defined
+    CALL sub
+loop: JP loop
+
+sub:
+    ADD V0, 1
+    RET
+data: DB 0x80, 0b1, 255
+      DW sub, 0x1234
+";
+
+    #[test]
+    fn assemble_program() {
+        let (rom, symbols) = assemble(PROGRAM).unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE, 0x80, 0x01, 0xFF, 0x02,
+                0x06, 0x12, 0x34
+            ]
+        );
+        assert_eq!(
+            symbols.labels,
+            BTreeMap::from([
+                ("data".to_string(), 0x20A),
+                ("loop".to_string(), 0x204),
+                ("start".to_string(), 0x200),
+                ("sub".to_string(), 0x206),
+            ])
+        );
+        assert_eq!(
+            symbols.lines,
+            [
+                (0x200, 3),
+                (0x202, 4),
+                (0x204, 5),
+                (0x206, 8),
+                (0x208, 9),
+                (0x20A, 10),
+                (0x20D, 11)
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        let labels = BTreeMap::new();
+        let instruction = |text| assemble_instruction(text, &labels, parse_number);
+
+        assert_eq!(instruction("JP V0, 0x300"), Ok(0xB300));
+
+        // The SUPER-CHIP takes the register from the address
+        assert_eq!(instruction("JP V2, 0x234"), Ok(0xB234));
+        assert!(instruction("JP V3, 0x234").is_err());
+    }
+
+    #[test]
+    fn special_operands() {
+        let labels = BTreeMap::from([("i_label".to_string(), 0x300)]);
+        let instruction = |text| assemble_instruction(text, &labels, parse_number);
+
+        assert_eq!(instruction("ld [i], v3"), Ok(0xF355));
+        assert_eq!(instruction("LD B, VA"), Ok(0xFA33));
+        assert_eq!(instruction("LD I, i_label"), Ok(0xA300));
+        assert_eq!(instruction("DRW V1, V2, 15"), Ok(0xD12F));
+        assert_eq!(instruction("SHR V4"), Ok(0x8446));
+    }
+
+    #[test]
+    fn errors() {
+        for (source, error) in [
+            (
+                "JP nowhere",
+                "line 1: unknown label or invalid number `nowhere`",
+            ),
+            ("a:\na: CLS", "line 2: label `a` defined twice"),
+            ("1a: CLS", "line 1: invalid label `1a`"),
+            ("CLS\nLD V0, 256", "line 2: 0x100 doesn't fit in a byte"),
+            ("JP 0x1000", "line 1: 0x1000 is not a 12-bit address"),
+            (
+                "DRW V0, V1, 16",
+                "line 1: invalid instruction `DRW V0, V1, 16`",
+            ),
+            ("FOO V0", "line 1: invalid instruction `FOO V0`"),
+        ] {
+            assert_eq!(assemble(source).err().as_deref(), Some(error));
+        }
+    }
+
+    #[test]
+    fn program_too_large() {
+        let source = "DB 0\n".repeat(RAM_SIZE - ROM_ADDRESS as usize);
+
+        assert!(assemble(&source).is_ok());
+        assert_eq!(
+            assemble(&format!("{}CLS", source)).err().as_deref(),
+            Some("line 3585: the program doesn't fit in memory")
+        );
+    }
+
+    #[test]
+    fn symbols_round_trip() {
+        let (_, symbols) = assemble(PROGRAM).unwrap();
+        let rom_path = env::temp_dir().join(format!("chip8-assembler-{}.ch8", std::process::id()));
+
+        symbols.save(&rom_path).unwrap();
+
+        let loaded = Symbols::load(&rom_path);
+
+        fs::remove_file(rom_path.with_extension("sym")).unwrap();
+
+        let symbols = loaded.unwrap();
+
+        assert_eq!(symbols.line_at(0x206), Some(8));
+        assert_eq!(symbols.line_at(0x207), None);
+
+        // A breakpoint on a label or on a comment is moved to the next instruction
+        assert_eq!(symbols.address_at_line(7), Some((0x206, 8)));
+        assert_eq!(symbols.address_at_line(1), Some((0x200, 3)));
+        assert_eq!(symbols.address_at_line(12), None);
+
+        assert_eq!(symbols.describe(0x208), "sub+2");
+        assert_eq!(
+            symbols.disassemble(Operation::CallSubroutineAt { address: 0x206 }),
+            "CALL sub"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use std::{error::Error, time::Instant};
+
+use crate::{cli::BenchArgs, cpu::Cpu};
+
+// Run the ROM as fast as possible, without any key pressed, first decoding every instruction and then using the decoded
+// instruction cache.
+pub fn run(args: BenchArgs, rom: Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let mut results = Vec::new();
+
+    for (label, decode_cache) in [("Without cache", false), ("With cache", true)] {
+        let mut cpu = Cpu::with_seed(rom.clone(), args.super_chip, 0)?;
+
+        cpu.set_decode_cache(decode_cache);
+
+        let start = Instant::now();
+
+        for step in 1..=args.steps {
+            cpu.step();
+
+            if step % args.ipf == 0 {
+                cpu.decrement_timers();
+            }
+        }
+
+        let instructions_per_second = args.steps as f64 / start.elapsed().as_secs_f64();
+
+        println!(
+            "{:<14} {:>14.0} instructions per second",
+            label, instructions_per_second
+        );
+        results.push(instructions_per_second);
+    }
+
+    println!("{:<14} {:>14.2}x", "Speedup", results[1] / results[0]);
+
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use std::{collections::BTreeMap, error::Error, fs, path::PathBuf};
+
+use crate::{
+    config,
+    cpu::{Cpu, RAM_SIZE},
+};
+
+// Bytes of memory frozen to a value, e.g. the number of lives of the player. They're written again before every frame,
+// so whatever the program writes to them is undone.
+//
+// The cheats of a ROM are saved in the `cheats` directory of the configuration directory, in a file named after the
+// SHA-1 of the ROM (so that they still apply when the ROM is renamed), with one `address=value` line per byte, both in
+// hexadecimal. Lines starting with `#` are comments, e.g.:
+//
+//     # Infinite lives
+//     2F4=05
+#[derive(Default)]
+pub struct Cheats {
+    pub freezes: BTreeMap<u16, u8>,
+}
+
+impl Cheats {
+    // Load the cheats of a ROM, if any.
+    pub fn load(rom: &[u8]) -> Result<Cheats, Box<dyn Error>> {
+        let path = match path(rom) {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Cheats::default()),
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+
+        Cheats::parse(&content)
+            .map_err(|error| format!("Invalid cheats in {}: {}", path.display(), error).into())
+    }
+
+    // Save the cheats of a ROM, returning the path of the file.
+    pub fn save(&self, rom: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+        let path = path(rom).ok_or("Cannot find the configuration directory")?;
+        let content: String = self
+            .freezes
+            .iter()
+            .map(|(address, value)| format!("{:03X}={:02X}\n", address, value))
+            .collect();
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        fs::write(&path, content)
+            .map_err(|error| format!("Cannot write {}: {}", path.display(), error))?;
+
+        Ok(path)
+    }
+
+    fn parse(content: &str) -> Result<Cheats, String> {
+        let mut freezes = BTreeMap::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (address, value) = line
+                .split_once('=')
+                .and_then(|(address, value)| {
+                    let address = u16::from_str_radix(address.trim(), 16).ok()?;
+                    let value = u8::from_str_radix(value.trim(), 16).ok()?;
+
+                    Some((address, value))
+                })
+                .filter(|(address, _)| (*address as usize) < RAM_SIZE)
+                .ok_or(format!("line {}: expected `address=value`", index + 1))?;
+
+            freezes.insert(address, value);
+        }
+
+        Ok(Cheats { freezes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.freezes.is_empty()
+    }
+
+    // Write the frozen values, at the start of every frame.
+    pub fn apply(&self, cpu: &mut Cpu) {
+        for (address, value) in &self.freezes {
+            cpu.write(*address, *value);
+        }
+    }
+}
+
+fn path(rom: &[u8]) -> Option<PathBuf> {
+    let file_name = format!("{}.cht", crate::rom_hash(rom));
+
+    config::directory().map(|directory| directory.join("cheats").join(file_name))
+}
+
+// The candidates of a search are listed, and can be frozen while a ROM is running, once there are at most this many of
+// them.
+pub const MAX_CANDIDATES_SHOWN: usize = 16;
+
+// How the bytes of memory must have changed since the last search to remain candidates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+// A search for the address of a value, e.g. the number of lives, by narrowing down the candidates as the value changes
+// in the game: all the bytes of memory are candidates at first, and only those that changed in the same way remain
+// after each search.
+pub struct Search {
+    candidates: Vec<u16>,
+
+    // The memory when the last search was made, to compare the next one with.
+    snapshot: Vec<u8>,
+}
+
+impl Search {
+    pub fn new(ram: &[u8]) -> Search {
+        Search {
+            candidates: (0..ram.len() as u16).collect(),
+            snapshot: ram.to_vec(),
+        }
+    }
+
+    pub fn filter(&mut self, ram: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+
+        self.candidates.retain(|address| {
+            let (previous, value) = (snapshot[*address as usize], ram[*address as usize]);
+
+            match comparison {
+                Comparison::Equal(expected) => value == expected,
+                Comparison::Changed => value != previous,
+                Comparison::Unchanged => value == previous,
+                Comparison::Increased => value > previous,
+                Comparison::Decreased => value < previous,
+            }
+        });
+        self.snapshot = ram.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+// The searches and freezes made with hotkeys while a ROM is running, without the monitor: F5 starts a search, F6, F7
+// and F8 keep the candidates that decreased, increased or didn't change (or changed, with Shift), F4 freezes the
+// remaining candidates to their current value and Shift+F4 saves the frozen bytes as the cheats of the ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cheat {
+    Search,
+    Filter(Comparison),
+    Freeze,
+    Save,
+}
+
+impl Cheat {
+    // The cheat of a function key, e.g. 5 for F5.
+    pub fn from_key(number: u8, shift: bool) -> Option<Cheat> {
+        let cheat = match (number, shift) {
+            (4, false) => Cheat::Freeze,
+            (4, true) => Cheat::Save,
+            (5, false) => Cheat::Search,
+            (6, false) => Cheat::Filter(Comparison::Decreased),
+            (7, false) => Cheat::Filter(Comparison::Increased),
+            (8, false) => Cheat::Filter(Comparison::Unchanged),
+            (8, true) => Cheat::Filter(Comparison::Changed),
+            _ => return None,
+        };
+
+        Some(cheat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search() {
+        let mut ram = vec![0; 8];
+
+        ram[..4].copy_from_slice(&[3, 3, 3, 3]);
+
+        let mut search = Search::new(&ram);
+
+        assert_eq!(search.candidates().len(), 8);
+
+        ram[..4].copy_from_slice(&[2, 4, 3, 2]);
+        search.filter(&ram, Comparison::Changed);
+        assert_eq!(search.candidates(), [0, 1, 3]);
+
+        // Compared with the memory of the last search, not the first one
+        ram[..4].copy_from_slice(&[1, 5, 3, 2]);
+        search.filter(&ram, Comparison::Decreased);
+        assert_eq!(search.candidates(), [0]);
+
+        let mut search = Search::new(&ram);
+
+        ram[..4].copy_from_slice(&[1, 6, 4, 2]);
+        search.filter(&ram, Comparison::Increased);
+        assert_eq!(search.candidates(), [1, 2]);
+
+        search.filter(&ram, Comparison::Unchanged);
+        assert_eq!(search.candidates(), [1, 2]);
+
+        search.filter(&ram, Comparison::Equal(4));
+        assert_eq!(search.candidates(), [2]);
+    }
+
+    #[test]
+    fn parse() {
+        let cheats = Cheats::parse("# Infinite lives\n2F4=05\n\n  300 = ff  \n").unwrap();
+
+        assert_eq!(
+            cheats.freezes,
+            BTreeMap::from([(0x2F4, 0x05), (0x300, 0xFF)])
+        );
+        assert!(Cheats::parse("").unwrap().is_empty());
+
+        assert_eq!(
+            Cheats::parse("2F4=05\n2F4").err().unwrap(),
+            "line 2: expected `address=value`"
+        );
+        assert!(Cheats::parse("2F4=100").is_err());
+        assert!(Cheats::parse(&format!("{:X}=00", RAM_SIZE)).is_err());
+    }
+
+    #[test]
+    fn freeze() {
+        let mut cpu = Cpu::new(vec![0x12, 0x00], false).unwrap();
+        let cheats = Cheats::parse("300=05\n301=06").unwrap();
+
+        cpu.write(0x300, 1);
+        cheats.apply(&mut cpu);
+
+        assert_eq!(cpu.ram()[0x300..0x303], [5, 6, 0]);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(Cheat::from_key(5, false), Some(Cheat::Search));
+        assert_eq!(
+            Cheat::from_key(6, false),
+            Some(Cheat::Filter(Comparison::Decreased))
+        );
+        assert_eq!(
+            Cheat::from_key(8, true),
+            Some(Cheat::Filter(Comparison::Changed))
+        );
+        assert_eq!(Cheat::from_key(4, true), Some(Cheat::Save));
+        assert_eq!(Cheat::from_key(5, true), None);
+        assert_eq!(Cheat::from_key(9, false), None);
+    }
+}
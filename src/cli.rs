@@ -1,12 +1,362 @@
-use clap::Parser;
+use std::fmt;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use winit::event::VirtualKeyCode;
+
+use crate::key_mapping::{parse_binding, GamepadButton, Layout};
+use crate::palette::{Color, Theme};
+use crate::persistence::{parse_decay_rate, Persistence};
+use crate::tui::Glyphs;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Path to the configuration file (defaults to chip8/config.toml in the user's configuration directory)
+    #[clap(long, global = true, value_name = "CONFIG_PATH")]
+    pub config: Option<std::path::PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a ROM in a window
+    Run(RunArgs),
+
+    /// Run a ROM under two quirk profiles in lockstep and report the first divergence
+    Diff(DiffArgs),
+
+    /// Measure the instructions per second with and without the decode cache
+    Bench(BenchArgs),
+
+    /// Run a ROM without a window for a number of frames and save the screen to a PNG file
+    Screenshot(ScreenshotArgs),
+
+    /// Replay a movie without a window and record it to an animated GIF or to a Y4M video
+    Replay(ReplayArgs),
+
+    /// Serve the Debug Adapter Protocol over stdio or TCP, to debug ROMs from an editor
+    Dap(DapArgs),
+
+    /// Assemble a source file to a ROM, with a symbol file for the debuggers and the profiler
+    Assemble(AssembleArgs),
+
+    /// Examine and modify the memory and the registers of a paused ROM from a console
+    Monitor(MonitorArgs),
+
+    /// Create IPS and BPS patches
+    Patch(PatchArgs),
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Run as the SUPER-CHIP, like --platform schip
+    #[clap(short, long, action, default_value_t = false)]
+    pub super_chip: bool,
+
+    /// Interpreter whose quirks the ROM is run with, taking precedence over the settings of the ROM and the database
+    #[clap(long, value_enum, conflicts_with = "super-chip")]
+    pub platform: Option<Profile>,
+
+    /// Write an execution profile and an annotated disassembly to this file on exit
+    #[clap(long, value_name = "REPORT_PATH")]
+    pub profile: Option<std::path::PathBuf>,
+
+    /// Keep executing wait loops instead of sleeping until the next timer tick or key press
+    #[clap(long, action, default_value_t = false)]
+    pub no_idle_skip: bool,
+
+    /// Number of instructions executed per frame (60 frames per second) [default: 10]
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ipf: Option<u32>,
+
+    /// Size of a pixel of the CHIP-8 when the window is opened [default: the size of the window when it was last closed,
+    /// or 10]
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub scale: Option<u32>,
+
+    /// Run in the terminal instead of a window, e.g. over SSH
+    #[clap(long, action, default_value_t = false)]
+    pub tui: bool,
+
+    /// Characters used to draw the screen in the terminal
+    #[clap(long, value_enum, value_name = "GLYPHS", default_value_t = Glyphs::HalfBlocks)]
+    pub tui_glyphs: Glyphs,
+
+    /// Debug the ROM in the terminal, paused from the start, with the disassembly, the registers, the stack and the
+    /// memory
+    #[clap(long, action, default_value_t = false, conflicts_with_all = &["replay", "record", "record-movie", "profile"])]
+    pub debug: bool,
+
+    /// Keyboard layout used to map the keypad [default: qwerty]
+    #[clap(long, value_enum)]
+    pub layout: Option<Layout>,
+
+    /// Map the keypad by the physical position of the keys, whatever the keyboard layout
+    #[clap(long, action, default_value_t = false)]
+    pub physical_keys: bool,
+
+    /// Bind a key to a keypad value for this ROM, in addition to the layout (e.g. --bind Up=5)
+    #[clap(long = "bind", value_name = "KEY=VALUE", value_parser = parse_binding::<VirtualKeyCode>)]
+    pub bindings: Vec<(VirtualKeyCode, u8)>,
+
+    /// Bind a gamepad button to a keypad value for this ROM, replacing the default buttons (e.g. --bind-button South=6)
+    #[clap(long = "bind-button", value_name = "BUTTON=VALUE", value_parser = parse_binding::<GamepadButton>)]
+    pub button_bindings: Vec<(GamepadButton, u8)>,
+
+    #[clap(flatten)]
+    pub palette: PaletteArgs,
+
+    /// Keep the pixels lit for a while after they're turned off, to reduce flickering [default: none]
+    #[clap(long, value_enum, value_name = "MODE")]
+    pub persistence: Option<Persistence>,
+
+    /// Fraction of its brightness lost by a pixel at every frame once it's turned off, with --persistence fade
+    /// [default: 0.4]
+    #[clap(long, value_name = "RATE", value_parser = parse_decay_rate)]
+    pub decay_rate: Option<f32>,
+
+    /// Show the frames drawn per second, the instructions executed per frame and the speed of the emulation (press F3
+    /// to show or hide them)
+    #[clap(long, action, default_value_t = false)]
+    pub stats: bool,
+
+    /// Directory where the screenshots taken with F12 and the GIFs recorded with F9 are saved
+    #[clap(long, value_name = "DIRECTORY", default_value = ".")]
+    pub screenshot_dir: std::path::PathBuf,
+
+    /// Size of a pixel of the CHIP-8 in the screenshots
+    #[clap(long, value_name = "SCALE", default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub screenshot_scale: u32,
+
+    /// Record the screen to an animated GIF, or to a Y4M video and a WAV audio track, from the start (press F9 to stop
+    /// or start recording)
+    #[clap(long, value_name = "GIF_OR_Y4M_PATH")]
+    pub record: Option<std::path::PathBuf>,
+
+    /// Size of a pixel of the CHIP-8 in the recordings
+    #[clap(long, value_name = "SCALE", default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub record_scale: u32,
+
+    /// Record the key presses to a movie file, which can be replayed with --replay or with the replay subcommand
+    #[clap(long, value_name = "MOVIE_PATH")]
+    pub record_movie: Option<std::path::PathBuf>,
+
+    /// Replay a movie recorded with --record-movie, ignoring the keys pressed until its end
+    #[clap(long, value_name = "MOVIE_PATH", conflicts_with = "record-movie")]
+    pub replay: Option<std::path::PathBuf>,
+
+    /// Apply an IPS or a BPS patch to the ROM when it's loaded (can be given several times, the patches being applied
+    /// in order)
+    #[clap(long = "patch", value_name = "PATCH_PATH")]
+    pub patches: Vec<std::path::PathBuf>,
+
+    /// Don't apply the cheats saved for this ROM (they're never applied while a movie is recorded or replayed)
+    #[clap(long, action, default_value_t = false)]
+    pub no_cheats: bool,
+
+    /// Don't look up the ROM in the database for its title and the settings to run it with
+    #[clap(long, action, default_value_t = false)]
+    pub no_database: bool,
+
+    /// Tell when the ROM database is missing, and explain how the platform of the ROM was guessed from its instructions
+    /// when it's not given by the settings or the database
+    #[clap(short, long, action, default_value_t = false)]
+    pub verbose: bool,
+
+    /// Save the settings of this run (SUPER-CHIP, IPF, key and button bindings) next to the ROM to reuse them next time
+    #[clap(long, action, default_value_t = false)]
+    pub save_settings: bool,
+
+    /// Path to the ROM file
+    pub rom_path: std::path::PathBuf,
+}
+
+#[derive(Args)]
+pub struct PaletteArgs {
+    /// Color theme of the display, which can be cycled at runtime with F2 [default: classic]
+    #[clap(long, value_enum, value_name = "THEME")]
+    pub palette: Option<Theme>,
+
+    /// Color of the pixels that are on, replacing the one of the theme (e.g. --foreground "#33FF33")
+    #[clap(long, value_name = "#RRGGBB", value_parser = str::parse::<Color>)]
+    pub foreground: Option<Color>,
+
+    /// Color of the pixels that are off, replacing the one of the theme
+    #[clap(long, value_name = "#RRGGBB", value_parser = str::parse::<Color>)]
+    pub background: Option<Color>,
+}
+
+#[derive(Args)]
+pub struct ScreenshotArgs {
     /// Run as the SUPER-CHIP
     #[clap(short, long, action, default_value_t = false)]
     pub super_chip: bool,
 
+    /// Number of instructions executed per frame (60 frames per second) [default: 10]
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ipf: Option<u32>,
+
+    /// Number of frames to run before taking the screenshot
+    #[clap(long, default_value_t = 300)]
+    pub frames: u32,
+
+    /// Size of a pixel of the CHIP-8 in the screenshot
+    #[clap(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub scale: u32,
+
+    #[clap(flatten)]
+    pub palette: PaletteArgs,
+
+    /// Path of the PNG file [default: the name of the ROM followed by the date and time, in the current directory]
+    #[clap(short, long, value_name = "PNG_PATH")]
+    pub output: Option<std::path::PathBuf>,
+
     /// Path to the ROM file
     pub rom_path: std::path::PathBuf,
 }
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Path of the GIF or Y4M file (a WAV audio track is written next to a Y4M video)
+    #[clap(short, long, value_name = "GIF_OR_Y4M_PATH")]
+    pub output: std::path::PathBuf,
+
+    /// Size of a pixel of the CHIP-8 in the recording
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub scale: u32,
+
+    #[clap(flatten)]
+    pub palette: PaletteArgs,
+
+    /// Path to the movie file recorded with run --record-movie
+    pub movie_path: std::path::PathBuf,
+
+    /// Path to the ROM file
+    pub rom_path: std::path::PathBuf,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Quirk profile to compare (must be given twice)
+    #[clap(short, long = "profile", value_enum, required = true)]
+    pub profiles: Vec<Profile>,
+
+    /// Seed shared by the random number generators of both instances
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Maximum number of instructions to execute
+    #[clap(long, default_value_t = 1_000_000)]
+    pub steps: u64,
+
+    /// Number of instructions executed between two timer decrements
+    #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub ipf: u64,
+
+    /// Path to the ROM file
+    pub rom_path: std::path::PathBuf,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Run as the SUPER-CHIP
+    #[clap(short, long, action, default_value_t = false)]
+    pub super_chip: bool,
+
+    /// Number of instructions to execute for each measure
+    #[clap(long, default_value_t = 10_000_000)]
+    pub steps: u64,
+
+    /// Number of instructions executed between two timer decrements
+    #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub ipf: u64,
+
+    /// Path to the ROM file
+    pub rom_path: std::path::PathBuf,
+}
+
+// The quirks of an interpreter are grouped in profiles named after the platform that introduced them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Profile {
+    /// The original CHIP-8 interpreter of the COSMAC VIP
+    Vip,
+
+    /// The SUPER-CHIP interpreter of the HP 48 calculators
+    Schip,
+}
+
+impl Profile {
+    pub fn is_super_chip(&self) -> bool {
+        *self == Profile::Schip
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Vip => write!(f, "vip"),
+            Profile::Schip => write!(f, "schip"),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct DapArgs {
+    /// Listen for a client on this TCP port of the local host instead of talking over stdio
+    #[clap(long)]
+    pub port: Option<u16>,
+}
+
+#[derive(Args)]
+pub struct AssembleArgs {
+    /// Path of the ROM, next to which the symbol file is written [default: the path of the source with the .ch8
+    /// extension]
+    #[clap(short, long, value_name = "ROM_PATH")]
+    pub output: Option<std::path::PathBuf>,
+
+    /// Path to the source file
+    pub source_path: std::path::PathBuf,
+}
+
+#[derive(Args)]
+pub struct MonitorArgs {
+    /// Run as the SUPER-CHIP
+    #[clap(short, long, action, default_value_t = false)]
+    pub super_chip: bool,
+
+    /// Number of instructions executed between two timer decrements [default: 10]
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ipf: Option<u32>,
+
+    /// Path to the ROM file
+    pub rom_path: std::path::PathBuf,
+}
+
+#[derive(Args)]
+pub struct PatchArgs {
+    #[clap(subcommand)]
+    pub command: PatchCommand,
+}
+
+#[derive(Subcommand)]
+pub enum PatchCommand {
+    /// Create a patch turning a ROM into a modified one, in the IPS format if the output ends with .ips and in the BPS
+    /// format otherwise
+    Create(PatchCreateArgs),
+}
+
+#[derive(Args)]
+pub struct PatchCreateArgs {
+    /// Path of the patch
+    #[clap(short, long, value_name = "PATCH_PATH")]
+    pub output: std::path::PathBuf,
+
+    /// Path to the original ROM
+    pub original_path: std::path::PathBuf,
+
+    /// Path to the modified ROM
+    pub modified_path: std::path::PathBuf,
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, error::Error, fs, path::Path, path::PathBuf};
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::key_mapping::{self, Layout};
+use crate::palette::{Color, Theme};
+use crate::persistence::Persistence;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+// The configuration file is optional and every setting in it has a default value. Settings given on the command line
+// take precedence over the ones found in this file.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keyboard: KeyboardConfig,
+    pub display: DisplayConfig,
+    pub window: WindowConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    // Built-in layout used to map the keypad.
+    pub layout: Option<Layout>,
+
+    // Map the keypad by the physical position of the keys instead of their labels.
+    pub physical: bool,
+
+    // User-defined mapping from key names (as in `VirtualKeyCode`) to keypad values, replacing the built-in layout.
+    #[serde(deserialize_with = "key_mapping::deserialize_keys")]
+    pub keys: HashMap<VirtualKeyCode, u8>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    // Built-in color theme.
+    pub palette: Option<Theme>,
+
+    // All four colors of the palette (background, foreground and the two extra colors of XO-CHIP), replacing the ones
+    // of the theme.
+    pub colors: Option<[Color; 4]>,
+
+    // Colors of the pixels that are on and off, replacing the ones of the theme or of `colors`.
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+
+    // Rendering mode reducing the flickering, and the fraction of its brightness lost by a pixel at every frame in
+    // `fade` mode.
+    pub persistence: Option<Persistence>,
+    pub decay_rate: Option<f32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    // Size of a pixel of the CHIP-8 when the window is opened, replacing the size it had when it was last closed.
+    pub scale: Option<u32>,
+}
+
+impl Config {
+    // Load the configuration from the given file, or from `chip8/config.toml` in the user's configuration directory if
+    // it exists.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+
+        toml::from_str(&content).map_err(|error| {
+            format!("Invalid configuration in {}: {}", path.display(), error).into()
+        })
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    directory().map(|directory| directory.join(CONFIG_FILE_NAME))
+}
+
+// The directory of the configuration file and of the other files managed by the emulator, e.g. `~/.config/chip8` on
+// Linux.
+pub fn directory() -> Option<PathBuf> {
+    dirs::config_dir().map(|directory| directory.join("chip8"))
+}
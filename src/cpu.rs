@@ -1,32 +1,30 @@
-use std::time::{Duration, Instant};
-
-use crate::{font::FONT, operation::Operation, SCREEN_HEIGHT, SCREEN_WIDTH};
-use rand::random;
+use crate::{font::FONT, nibble::Nibble, operation::Operation, SCREEN_HEIGHT, SCREEN_WIDTH};
+use rand::{random, rngs::StdRng, Rng, SeedableRng};
 
 // The first CHIP-8 interpreter (on the COSMAC VIP computer) was also located in RAM, from address 000 to 1FF. It would
 //  expect a CHIP-8 program to be loaded into memory after it, starting at address 200.
-const ROM_ADDRESS: u16 = 0x200;
+pub const ROM_ADDRESS: u16 = 0x200;
 
 // The memory should be 4 kB (4 kilobytes, ie. 4096 bytes) large.
-const RAM_SIZE: usize = 0x1000;
+pub const RAM_SIZE: usize = 0x1000;
+
+// The ROM is loaded after the interpreter, so it has to fit in the rest of the memory.
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - ROM_ADDRESS as usize;
 const VRAM_SIZE: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize;
 
 // Original interpreters had limited space on the stack; usually at least 16 two-byte entries.
 const STACK_SIZE: usize = 16;
 
-// Timers should be decremented by one 60 times per second (ie. at 60 Hz). This is independent of the speed of the
-// fetch/decode/execute loop.
-const TIMER_FREQUENCY: Duration = Duration::from_micros(1000000 / 60); // 60 times per second
-
 // We should store the font data in memory, because games will draw these characters like regular sprites: They set the
 // index register I to the character's memory location and then draw it. There's a special instruction for setting I to
 // a character's address, so we can choose where to put it. Anywhere in the first 512 bytes (000–1FF) is fine. For some
 // reason, it's become popular to put it at 050–09F, so you can follow that convention if you want.
 const FONT_ADDRESS: u16 = 0x050;
 
+#[derive(Clone)]
 pub struct Cpu {
     // CHIP-8 has direct access to up to 4 kilobytes of RAM.
-    ram: [u8; RAM_SIZE as usize],
+    ram: [u8; RAM_SIZE],
 
     // A program counter which points at the current instruction in memory.
     pc: u16,
@@ -54,14 +52,35 @@ pub struct Cpu {
     // Flag set to `true` if the SUPER-CHIP behavior is enabled
     super_chip: bool,
 
-    last_tick: Instant,
-    key_held: Option<u8>,
+    // Operations already decoded, indexed by their address. An entry is invalidated when one of the two bytes of its
+    // instruction is written to, so that self-modifying code keeps working.
+    decoded: Vec<Option<Operation>>,
+    decode_cache: bool,
+
+    // The random number generator is seeded explicitly so that two instances can be run in lockstep, and so that a
+    // session can be replayed.
+    seed: u64,
+    rng: StdRng,
+
+    // The state of each of the 16 keys of the hexadecimal keypad.
+    keys: [bool; 16],
+
+    // One bit per key checked by the program with EX9E or EXA1 since the start.
+    keys_read: u16,
 }
 
 impl Cpu {
-    pub fn new(rom: Vec<u8>, super_chip: bool) -> Cpu {
-        if rom.len() > RAM_SIZE {
-            panic!("ROM size cannot be larger than {} bytes", RAM_SIZE);
+    pub fn new(rom: Vec<u8>, super_chip: bool) -> Result<Cpu, String> {
+        Cpu::with_seed(rom, super_chip, random())
+    }
+
+    pub fn with_seed(rom: Vec<u8>, super_chip: bool, seed: u64) -> Result<Cpu, String> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(format!(
+                "The ROM is {} bytes long, but only {} bytes fit in the memory",
+                rom.len(),
+                MAX_ROM_SIZE
+            ));
         }
 
         let mut ram = [0x00; RAM_SIZE];
@@ -80,7 +99,7 @@ impl Cpu {
             i += 1;
         }
 
-        Cpu {
+        Ok(Cpu {
             ram,
             pc: ROM_ADDRESS,
             i: 0x00,
@@ -91,20 +110,94 @@ impl Cpu {
             vram: [0x00; VRAM_SIZE],
             vram_changed: false,
             super_chip,
-            last_tick: Instant::now(),
-            key_held: Option::None,
-        }
+            decoded: vec![None; RAM_SIZE],
+            decode_cache: true,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            keys: [false; 16],
+            keys_read: 0,
+        })
     }
 
     fn fetch(&mut self) -> u16 {
-        let high = self.ram[self.pc as usize];
-        let low = self.ram[(self.pc + 1) as usize];
+        let instruction = self.instruction_at(self.pc);
 
         self.pc += 2;
 
+        instruction
+    }
+
+    pub fn instruction_at(&self, address: u16) -> u16 {
+        let high = self.ram[address as usize];
+        let low = self.ram[(address + 1) as usize];
+
         ((high as u16) << 8) | low as u16
     }
 
+    // Decode the instruction at the current PC without executing it.
+    pub fn peek(&self) -> Operation {
+        self.peek_at(self.pc)
+    }
+
+    pub fn peek_at(&self, address: u16) -> Operation {
+        Operation::decode(self.instruction_at(address), self.super_chip)
+    }
+
+    // Like `peek_at`, for an address that may hold data rather than an instruction.
+    pub fn try_peek_at(&self, address: u16) -> Option<Operation> {
+        Operation::try_decode(self.instruction_at(address), self.super_chip)
+    }
+
+    // Why the instruction at PC would make the CPU panic, if it would, so that debuggers can stop before executing it.
+    pub fn fault(&self) -> Option<String> {
+        if self.pc as usize >= RAM_SIZE - 1 {
+            return Some(format!(
+                "PC 0x{:03X} is past the end of the memory",
+                self.pc
+            ));
+        }
+
+        match self.try_peek_at(self.pc) {
+            None => Some(format!(
+                "Unsupported instruction {:04X} at 0x{:03X}",
+                self.instruction_at(self.pc),
+                self.pc
+            )),
+            Some(Operation::ReturnFromSubroutine) if self.stack.is_empty() => {
+                Some(format!("Return with an empty stack at 0x{:03X}", self.pc))
+            }
+            Some(Operation::CallSubroutineAt { .. }) if self.stack.len() == STACK_SIZE => {
+                Some(format!(
+                    "Stack size limit of {} reached at 0x{:03X}",
+                    STACK_SIZE, self.pc
+                ))
+            }
+            Some(Operation::AddVXToI { x })
+                if self.i.checked_add(self.v[x as usize] as u16).is_none() =>
+            {
+                Some(format!("I overflows at 0x{:03X}", self.pc))
+            }
+
+            // The instructions reading or writing the memory at I must stay in it
+            Some(Operation::StoreBinaryCodedDecimalOfVX { .. }) => self.memory_fault(3),
+            Some(Operation::StoreFromV0ToVX { x }) | Some(Operation::FillFromV0ToVX { x }) => {
+                self.memory_fault(x as usize + 1)
+            }
+            Some(Operation::DrawSpriteAt { height, .. }) => self.memory_fault(height as usize),
+            _ => None,
+        }
+    }
+
+    fn memory_fault(&self, count: usize) -> Option<String> {
+        match self.i as usize + count > RAM_SIZE {
+            true => Some(format!(
+                "The {} bytes from I 0x{:03X} go past the end of the memory at 0x{:03X}",
+                count, self.i, self.pc
+            )),
+            false => None,
+        }
+    }
+
     fn execute(&mut self, operation: Operation) {
         match operation {
             // In the original CHIP-8 interpreters, this would pause execution of the CHIP-8 program and call a
@@ -259,7 +352,7 @@ impl Cpu {
             // Skips if the values in VX and VY are not equal.
             Operation::SkipNextInstructionIfVXNotEqualsVY { x, y } => {
                 if self.v[x as usize] != self.v[y as usize] {
-                    self.pc = self.pc + 2;
+                    self.pc += 2;
                 }
             }
 
@@ -284,7 +377,7 @@ impl Cpu {
 
             // This instruction generates a random number, binary ANDs it with the value NN, and puts the result in VX.
             Operation::SetVXToVXAndRandomNumber { x, value } => {
-                let random: u8 = random();
+                let random: u8 = self.rng.gen();
                 self.v[x as usize] = random & value;
             }
 
@@ -331,16 +424,19 @@ impl Cpu {
 
             // Skip the following instruction based on a condition. These skip based on whether the player is currently
             // pressing a key or not.
+            // Only the last nibble of VX is used to identify the key.
             Operation::SkipNextInstructionIfKeyInVXPressed { x } => {
-                self.pc += match self.key_held {
-                    Some(key) if key == self.v[x as usize] => 2,
-                    _ => 0,
+                self.keys_read |= 1 << (self.v[x as usize] & 0x0F);
+                self.pc += match self.keys[(self.v[x as usize] & 0x0F) as usize] {
+                    true => 2,
+                    false => 0,
                 }
             }
             Operation::SkipNextInstructionIfKeyInVXNotPressed { x } => {
-                self.pc += match self.key_held {
-                    Some(key) if key == self.v[x as usize] => 0,
-                    _ => 2,
+                self.keys_read |= 1 << (self.v[x as usize] & 0x0F);
+                self.pc += match self.keys[(self.v[x as usize] & 0x0F) as usize] {
+                    true => 0,
+                    false => 2,
                 }
             }
 
@@ -358,7 +454,7 @@ impl Cpu {
             // If a key is pressed while this instruction is waiting for input, its hexadecimal value will be put in VX
             // and execution continues.
             Operation::AwaitKeyPress { x } => {
-                self.pc -= match self.key_held {
+                self.pc -= match self.held_key() {
                     Some(key) => {
                         self.v[x as usize] = key;
                         0
@@ -393,7 +489,7 @@ impl Cpu {
             // interpreter just took the last nibble of VX and used that as the character.
             Operation::SetIToSpriteLocationForCharacterInVX { x } => {
                 let offset = self.v[x as usize] as u16 * 5;
-                self.i = FONT_ADDRESS + offset as u16;
+                self.i = FONT_ADDRESS + offset;
             }
 
             // It takes the number in VX (which is one byte, so it can be any number from 0 to 255) and converts it to
@@ -402,9 +498,9 @@ impl Cpu {
                 let n = self.v[x as usize];
                 let i = self.i as usize;
 
-                self.ram[i] = n / 100;
-                self.ram[i + 1] = (n % 100) / 10;
-                self.ram[i + 2] = n % 10;
+                self.write_ram(i, n / 100);
+                self.write_ram(i + 1, (n % 100) / 10);
+                self.write_ram(i + 2, n % 10);
             }
 
             // The value of each variable register from V0 to VX inclusive (if X is 0, then only V0) will be stored in
@@ -418,14 +514,14 @@ impl Cpu {
             Operation::StoreFromV0ToVX { x } => match self.super_chip {
                 false => {
                     for i in 0..=x {
-                        self.ram[self.i as usize] = self.v[i as usize];
+                        self.write_ram(self.i as usize, self.v[i as usize]);
                         self.i += 1;
                     }
                 }
                 true => {
                     for i in 0..=x {
                         let address = self.i + i as u16;
-                        self.ram[address as usize] = self.v[i as usize];
+                        self.write_ram(address as usize, self.v[i as usize]);
                     }
                 }
             },
@@ -449,58 +545,183 @@ impl Cpu {
         }
     }
 
+    fn write_ram(&mut self, address: usize, value: u8) {
+        self.ram[address] = value;
+
+        // The byte is either the high or the low byte of a cached instruction.
+        self.decoded[address] = None;
+        if address > 0 {
+            self.decoded[address - 1] = None;
+        }
+    }
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+        self.decoded
+            .iter_mut()
+            .for_each(|operation| *operation = None);
+    }
+
     // An emulator's main task is simple. It runs in an infinite loop, and does these three tasks in succession.
-    pub fn tick(&mut self) -> (bool, bool) {
-        // Fetch the instruction from memory at the current PC.
-        let instruction = self.fetch();
+    pub fn step(&mut self) -> Operation {
+        let operation = match self.decoded[self.pc as usize] {
+            Some(operation) if self.decode_cache => {
+                self.pc += 2;
+                operation
+            }
+            _ => {
+                let address = self.pc as usize;
 
-        // Decode the instruction to find out what the emulator should do.
-        let operation = Operation::decode(instruction, self.super_chip);
+                // Fetch the instruction from memory at the current PC.
+                let instruction = self.fetch();
+
+                // Decode the instruction to find out what the emulator should do.
+                let operation = Operation::decode(instruction, self.super_chip);
+
+                if self.decode_cache {
+                    self.decoded[address] = Some(operation);
+                }
+
+                operation
+            }
+        };
 
         // Execute the instruction and do what it tells you.
         self.execute(operation);
 
-        // Decrement timers only if enough time has passed
-        let mut should_beep = false;
+        operation
+    }
 
-        if self.last_tick.elapsed().gt(&TIMER_FREQUENCY) {
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
-            }
+    // Timers should be decremented by one 60 times per second (ie. at 60 Hz). This is independent of the speed of the
+    // fetch/decode/execute loop, so it's up to the caller to call this function at the right pace. It lets the caller
+    // know if it needs to beep.
+    pub fn decrement_timers(&mut self) -> bool {
+        let mut should_beep = false;
 
-            if self.sound_timer > 0 {
-                should_beep = true;
-                self.sound_timer -= 1;
-            }
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
 
-            // Tick
-            self.last_tick = Instant::now();
+        if self.sound_timer > 0 {
+            should_beep = true;
+            self.sound_timer -= 1;
         }
 
-        // Let the CPU consumer know if VRAM has changed and if it needs to beep
-        (self.vram_changed, should_beep)
+        should_beep
     }
 
-    pub fn draw(&mut self, screen: &mut [u8]) {
-        for (p, pixel) in self.vram.iter().zip(screen.chunks_exact_mut(4)) {
-            pixel[0] = 0xFF; // Red
-            pixel[1] = 0xFF; // Green
-            pixel[2] = 0xFF; // Blue
-            pixel[3] = match p {
-                0 => 0x00,
-                1 => 0xFF,
-                _ => panic!("Invalid VRAM value ({})", p),
-            }; // Alpha channel
+    // Many games spin on a FX07/3X00/1NNN loop until the delay timer reaches zero, or wait for a key with FX0A. Nothing
+    // but a timer tick or a key press can make such a program progress, so there is no need to keep executing it.
+    pub fn is_idle(&self) -> bool {
+        let instruction = self.instruction_at(self.pc);
+
+        match instruction.get_nibbles() {
+            (0x0F, _, 0x00, 0x0A) => self.is_waiting_for_key(),
+            (0x0F, x, 0x00, 0x07) if (self.pc as usize) + 6 <= RAM_SIZE => {
+                let skip = self.instruction_at(self.pc + 2);
+                let jump = self.instruction_at(self.pc + 4);
+
+                match skip.get_nibbles() {
+                    (0x03, skip_x, _, _) => {
+                        skip_x == x
+                            && jump == 0x1000 | self.pc
+                            && self.delay_timer > (skip & 0x00FF) as u8
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
         }
+    }
+
+    // The program is blocked on FX0A until any key is pressed.
+    pub fn is_waiting_for_key(&self) -> bool {
+        matches!(
+            self.instruction_at(self.pc).get_nibbles(),
+            (0x0F, _, 0x00, 0x0A)
+        ) && self.held_key().is_none()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn keys_read(&self) -> u16 {
+        self.keys_read
+    }
+
+    // Let the caller know if VRAM has changed since the last call.
+    pub fn take_vram_changed(&mut self) -> bool {
+        std::mem::take(&mut self.vram_changed)
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.keys[(key & 0x0F) as usize] = true;
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.keys[(key & 0x0F) as usize] = false;
+    }
+
+    // The lowest key currently held, if any.
+    fn held_key(&self) -> Option<u8> {
+        self.keys.iter().position(|held| *held).map(|key| key as u8)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    // The setters below change the state of a paused CPU, e.g. from the monitor.
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.write_ram(address as usize, value);
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
 
-        self.vram_changed = false;
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
     }
 
-    pub fn press_key(&mut self, value: u8) {
-        self.key_held = Option::Some(value);
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
     }
 
-    pub fn release_key(&mut self) {
-        self.key_held = Option::None;
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
     }
 }
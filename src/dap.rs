@@ -0,0 +1,1022 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    cli::DapArgs,
+    cpu::Cpu,
+    database,
+    operation::Operation,
+    platform,
+    settings::RomSettings,
+    stepper::{ReverseStop, Stepper, Watch},
+    symbols::Symbols,
+    DEFAULT_IPF, SCREEN_WIDTH,
+};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1000000 / 60);
+
+// The CHIP-8 has a single thread of execution.
+const THREAD_ID: u64 = 1;
+
+// Variables are grouped in scopes, each of them having a reference that the client uses to fetch its variables.
+const REGISTERS_REFERENCE: u64 = 1;
+const DISPLAY_REFERENCE: u64 = 2;
+
+// Serve the Debug Adapter Protocol (https://microsoft.github.io/debug-adapter-protocol/), so that ROMs can be debugged
+// from any editor supporting it. The client talks to the adapter over stdio, or over TCP when a port is given.
+pub fn run(args: DapArgs) -> Result<(), Box<dyn Error>> {
+    match args.port {
+        None => serve(io::stdin(), io::stdout()),
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+            eprintln!("Listening on {}", listener.local_addr()?);
+
+            let (stream, _) = listener.accept()?;
+
+            serve(stream.try_clone()?, stream)
+        }
+    }
+}
+
+fn serve(reader: impl Read + Send + 'static, writer: impl Write) -> Result<(), Box<dyn Error>> {
+    // Messages are read on their own thread, so that the program keeps running while waiting for them
+    let (sender, messages) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter {
+        writer,
+        seq: 0,
+        events: Vec::new(),
+        session: None,
+        stop_on_entry: false,
+    };
+
+    adapter.run(messages)
+}
+
+// Every message is a JSON object preceded by a header giving its length, e.g. `Content-Length: 119\r\n\r\n{...}`.
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Message>, Box<dyn Error>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let mut content = vec![0; length.ok_or("Missing Content-Length header")?];
+
+    reader.read_exact(&mut content)?;
+
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+#[derive(Deserialize)]
+struct Message {
+    seq: u64,
+
+    // Only requests are handled, the adapter never sending requests to the client.
+    #[serde(rename = "type")]
+    kind: String,
+
+    #[serde(default)]
+    command: String,
+
+    #[serde(default)]
+    arguments: Value,
+}
+
+struct Adapter<W: Write> {
+    writer: W,
+    seq: u64,
+
+    // Events sent once the response to the current request has been sent.
+    events: Vec<(&'static str, Value)>,
+
+    session: Option<Session>,
+    stop_on_entry: bool,
+}
+
+impl<W: Write> Adapter<W> {
+    fn run(&mut self, messages: Receiver<Message>) -> Result<(), Box<dyn Error>> {
+        let mut next_frame = Instant::now();
+
+        loop {
+            let running = self
+                .session
+                .as_ref()
+                .is_some_and(|session| session.run.is_some());
+            let message = match running {
+                true => match messages
+                    .recv_timeout(next_frame.saturating_duration_since(Instant::now()))
+                {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+                false => match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            if let Some(message) = message {
+                if message.kind != "request" {
+                    continue;
+                }
+
+                if !running {
+                    next_frame = Instant::now();
+                }
+
+                if !self.request(message)? {
+                    return Ok(());
+                }
+
+                continue;
+            }
+
+            if let Some(session) = &mut self.session {
+                if let Some(stop) = session.run_frame() {
+                    self.events.push(stopped_event(stop));
+                    self.flush_events()?;
+                }
+            }
+
+            next_frame += FRAME_DURATION;
+
+            // Don't try to catch up if we're late
+            let now = Instant::now();
+
+            if now > next_frame + FRAME_DURATION {
+                next_frame = now;
+            }
+        }
+    }
+
+    // Handle a request and send the response, returning `false` when the client disconnects.
+    fn request(&mut self, request: Message) -> Result<bool, Box<dyn Error>> {
+        let disconnect = matches!(request.command.as_str(), "disconnect" | "terminate");
+        let response = match self.handle(&request.command, &request.arguments) {
+            Ok(body) => json!({
+                "type": "response",
+                "request_seq": request.seq,
+                "success": true,
+                "command": request.command,
+                "body": body,
+            }),
+            Err(message) => json!({
+                "type": "response",
+                "request_seq": request.seq,
+                "success": false,
+                "command": request.command,
+                "message": message,
+            }),
+        };
+
+        self.send(response)?;
+        self.flush_events()?;
+
+        Ok(!disconnect)
+    }
+
+    fn handle(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => {
+                self.events.push(("initialized", json!({})));
+
+                return Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsSteppingGranularity": true,
+                    "supportsStepBack": true,
+                    "supportsDataBreakpoints": true,
+                    "supportsTerminateRequest": true,
+                }));
+            }
+            "launch" => {
+                self.session = Some(Session::launch(arguments)?);
+                self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+
+                return Ok(json!({}));
+            }
+            "setExceptionBreakpoints" => return Ok(json!({ "breakpoints": [] })),
+            "threads" => return Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "disconnect" => return Ok(json!({})),
+            "terminate" => {
+                self.events.push(("terminated", json!({})));
+
+                return Ok(json!({}));
+            }
+            _ => {}
+        }
+
+        let session = self.session.as_mut().ok_or("No ROM has been launched")?;
+
+        match command {
+            "configurationDone" => {
+                match self.stop_on_entry {
+                    true => self.events.push(stopped_event(Stop::new("entry"))),
+                    false => {
+                        if let Some(stop) = session.resume(Run::Continue) {
+                            self.events.push(stopped_event(stop));
+                        }
+                    }
+                }
+
+                Ok(json!({}))
+            }
+            "setBreakpoints" => Ok(session.set_source_breakpoints(arguments)),
+            "setInstructionBreakpoints" => Ok(session.set_instruction_breakpoints(arguments)),
+            "dataBreakpointInfo" => Ok(session.data_breakpoint_info(arguments)),
+            "setDataBreakpoints" => Ok(session.set_data_breakpoints(arguments)),
+            "continue" => {
+                if let Some(stop) = session.resume(Run::Continue) {
+                    self.events.push(stopped_event(stop));
+                }
+
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let cpu = session.stepper.cpu();
+                let depth = cpu.stack().len();
+                let call = matches!(
+                    cpu.try_peek_at(cpu.pc()),
+                    Some(Operation::CallSubroutineAt { .. })
+                );
+
+                // Every instruction is on its own line of the source, so every granularity steps over one instruction
+                let stop = match command {
+                    "next" if call => session.resume(Run::StepOver { depth }),
+                    "stepOut" if depth > 0 => session.resume(Run::StepOut { depth }),
+                    _ => Some(session.step()),
+                };
+
+                if let Some(stop) = stop {
+                    self.events.push(stopped_event(stop));
+                }
+
+                Ok(json!({}))
+            }
+            "stepBack" => {
+                session.run = None;
+                self.events.push(stopped_event(session.step_back()));
+
+                Ok(json!({}))
+            }
+            "reverseContinue" => {
+                session.run = None;
+                self.events.push(stopped_event(session.reverse_continue()));
+
+                Ok(json!({}))
+            }
+            "pause" => {
+                if session.run.take().is_some() {
+                    self.events.push(stopped_event(Stop::new("pause")));
+                }
+
+                Ok(json!({}))
+            }
+            "stackTrace" => Ok(session.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Display", "variablesReference": DISPLAY_REFERENCE, "expensive": false },
+                ]
+            })),
+            "variables" => Ok(session.variables(arguments["variablesReference"].as_u64())),
+            "readMemory" => session.read_memory(arguments),
+            "disassemble" => session.disassemble(arguments),
+            "evaluate" => session.evaluate(arguments["expression"].as_str().unwrap_or("")),
+            _ => Err(format!("Unsupported request {}", command)),
+        }
+    }
+
+    fn flush_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send(json!({ "type": "event", "event": event, "body": body }))?;
+        }
+
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let content = message.to_string();
+
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.writer.flush()
+    }
+}
+
+// Why the program stopped, as reported to the client.
+struct Stop {
+    reason: &'static str,
+    description: Option<String>,
+}
+
+impl Stop {
+    fn new(reason: &'static str) -> Stop {
+        Stop {
+            reason,
+            description: None,
+        }
+    }
+}
+
+fn stopped_event(stop: Stop) -> (&'static str, Value) {
+    let mut body = json!({
+        "reason": stop.reason,
+        "threadId": THREAD_ID,
+        "allThreadsStopped": true,
+    });
+
+    if let Some(description) = stop.description {
+        body["description"] = json!(description);
+        body["text"] = json!(description);
+    }
+
+    ("stopped", body)
+}
+
+fn start_of_history() -> Stop {
+    Stop {
+        reason: "step",
+        description: Some("Reached the start of the history".to_string()),
+    }
+}
+
+fn data_breakpoint(watch: Watch, instruction: &str) -> Stop {
+    Stop {
+        reason: "data breakpoint",
+        description: Some(format!(
+            "{} changed by the instruction at {}",
+            watch, instruction
+        )),
+    }
+}
+
+// How the program runs until it's stopped by a breakpoint or a pause request.
+#[derive(Clone, Copy)]
+enum Run {
+    Continue,
+
+    // Until the subroutine called by the current instruction returns.
+    StepOver { depth: usize },
+
+    // Until the current subroutine returns.
+    StepOut { depth: usize },
+}
+
+struct Session {
+    stepper: Stepper,
+    symbols: Symbols,
+
+    // Breakpoints set on the lines of the source and on addresses in the disassembly.
+    source_breakpoints: BTreeSet<u16>,
+    instruction_breakpoints: BTreeSet<u16>,
+
+    // Registers and bytes of memory whose changes stop the program.
+    data_breakpoints: BTreeSet<Watch>,
+
+    // `None` while the program is stopped.
+    run: Option<Run>,
+}
+
+impl Session {
+    fn launch(arguments: &Value) -> Result<Session, String> {
+        let program = arguments["program"]
+            .as_str()
+            .map(PathBuf::from)
+            .ok_or("The program to debug is missing")?;
+        let rom = crate::load_rom(&program)
+            .map_err(|error| format!("Cannot read {}: {}", program.display(), error))?;
+        let settings = RomSettings::load(&program)
+            .and_then(|settings| Ok(settings.or(database::settings(&rom)?)))
+            .map_err(|error| error.to_string())?;
+        let super_chip = arguments["superChip"]
+            .as_bool()
+            .or(settings.super_chip)
+            .unwrap_or_else(|| platform::detect(&rom).super_chip());
+        let ipf = match &arguments["ipf"] {
+            Value::Null => settings.ipf.unwrap_or(DEFAULT_IPF),
+            ipf => ipf
+                .as_u64()
+                .and_then(|ipf| u32::try_from(ipf).ok())
+                .filter(|ipf| *ipf > 0)
+                .ok_or(format!(
+                    "Invalid ipf {}, expected a number from 1 to {}",
+                    ipf,
+                    u32::MAX
+                ))?,
+        };
+
+        let symbols = Symbols::load(&program).map_err(|error| error.to_string())?;
+
+        Ok(Session {
+            stepper: Stepper::new(Cpu::new(rom, super_chip)?, ipf),
+            symbols,
+            source_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            data_breakpoints: BTreeSet::new(),
+            run: None,
+        })
+    }
+
+    // Breakpoints on the lines of the source are resolved with the line map written by the assembler. A breakpoint on
+    // a line without any instruction is moved to the next one. Other sources cannot be resolved: Octo, in particular,
+    // doesn't write where the lines of a program end up in the ROM.
+    fn set_source_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"].as_str().map(Path::new);
+        let message = match path.and_then(Path::extension).is_some_and(|extension| extension == "8o") {
+            true => "Octo doesn't write a line map, set the breakpoint on an address in the disassembly instead",
+            false => "No line map for this source, assemble it with `chip8 assemble` or set the breakpoint on an address in the disassembly",
+        };
+        let same_source = match (path, &self.symbols.source) {
+            (Some(path), Some(source)) => fs::canonicalize(path)
+                .ok()
+                .is_some_and(|path| fs::canonicalize(source).ok() == Some(path)),
+            _ => false,
+        };
+
+        self.source_breakpoints.clear();
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or(0) as u32;
+
+                match self.symbols.address_at_line(line).filter(|_| same_source) {
+                    Some((address, line)) => {
+                        self.source_breakpoints.insert(address);
+
+                        json!({
+                            "verified": true,
+                            "line": line,
+                            "instructionReference": format!("0x{:03X}", address),
+                        })
+                    }
+                    None => json!({
+                        "verified": false,
+                        "line": line,
+                        "message": message,
+                    }),
+                }
+            })
+            .collect();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Value {
+        let ram_size = self.stepper.cpu().ram().len() as i64;
+
+        self.instruction_breakpoints.clear();
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|breakpoint| {
+                let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+                let address = parse_address(reference)
+                    .map(|address| address as i64 + breakpoint["offset"].as_i64().unwrap_or(0))
+                    .filter(|address| (0..ram_size - 1).contains(address));
+
+                match address {
+                    Some(address) => {
+                        self.instruction_breakpoints.insert(address as u16);
+
+                        json!({ "verified": true, "instructionReference": format!("0x{:03X}", address) })
+                    }
+                    None => json!({ "verified": false, "message": "Invalid address" }),
+                }
+            })
+            .collect();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // Data breakpoints can be set on the registers shown as variables (`V3`), and on bytes of memory given like in watch
+    // expressions (`[0x300]` or a label). They're identified by the register or the address.
+    fn data_breakpoint_info(&self, arguments: &Value) -> Value {
+        match self.parse_watch(arguments["name"].as_str().unwrap_or("")) {
+            Some(watch) => json!({
+                "dataId": watch.to_string(),
+                "description": format!("{} changes", watch),
+                "accessTypes": ["write"],
+                "canPersist": true,
+            }),
+            None => json!({
+                "dataId": null,
+                "description": "Data breakpoints can only be set on V0 to VF and on bytes of memory",
+            }),
+        }
+    }
+
+    fn set_data_breakpoints(&mut self, arguments: &Value) -> Value {
+        self.data_breakpoints.clear();
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(
+                |breakpoint| match self.parse_watch(breakpoint["dataId"].as_str().unwrap_or("")) {
+                    Some(watch) => {
+                        self.data_breakpoints.insert(watch);
+
+                        json!({ "verified": true })
+                    }
+                    None => json!({ "verified": false, "message": "Invalid register or address" }),
+                },
+            )
+            .collect();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn parse_watch(&self, name: &str) -> Option<Watch> {
+        Watch::parse(name, |address| {
+            let address = address
+                .strip_prefix('[')
+                .and_then(|address| address.strip_suffix(']'))
+                .unwrap_or(address);
+
+            self.symbols
+                .labels
+                .get(address)
+                .copied()
+                .or_else(|| parse_address(&address.to_lowercase()))
+        })
+    }
+
+    // Resume the execution. The instruction at the current address is always executed, so that the program can be
+    // resumed from a breakpoint.
+    fn resume(&mut self, run: Run) -> Option<Stop> {
+        self.run = Some(run);
+
+        if let Some(stop) = self.step_watching() {
+            return Some(stop);
+        }
+
+        self.check_run()
+    }
+
+    fn step(&mut self) -> Stop {
+        self.step_watching().unwrap_or(Stop::new("step"))
+    }
+
+    // Execute one instruction, stopping if it's unsupported or if it changes a data breakpoint.
+    fn step_watching(&mut self) -> Option<Stop> {
+        let pc = self.stepper.cpu().pc();
+
+        match self.stepper.step_watching(&self.data_breakpoints) {
+            Ok(None) => None,
+            Ok(Some(watch)) => {
+                self.run = None;
+
+                Some(data_breakpoint(watch, &self.symbols.annotate(pc)))
+            }
+            Err(message) => Some(self.exception(message)),
+        }
+    }
+
+    fn step_back(&mut self) -> Stop {
+        match self.stepper.step_back() {
+            true => Stop::new("step"),
+            false => start_of_history(),
+        }
+    }
+
+    // Go back to the last breakpoint reached, or to the last instruction changing a data breakpoint.
+    fn reverse_continue(&mut self) -> Stop {
+        let breakpoints = |pc| {
+            self.instruction_breakpoints.contains(&pc) || self.source_breakpoints.contains(&pc)
+        };
+
+        match self
+            .stepper
+            .reverse_continue(|cpu| breakpoints(cpu.pc()), &self.data_breakpoints)
+        {
+            ReverseStop::Breakpoint => Stop::new("breakpoint"),
+            ReverseStop::Watch(watch) => {
+                data_breakpoint(watch, &self.symbols.annotate(self.stepper.cpu().pc()))
+            }
+            ReverseStop::StartOfHistory => start_of_history(),
+        }
+    }
+
+    fn exception(&mut self, message: String) -> Stop {
+        self.run = None;
+
+        Stop {
+            reason: "exception",
+            description: Some(message),
+        }
+    }
+
+    // Stop once the subroutine stepped over or out of has returned.
+    fn check_run(&mut self) -> Option<Stop> {
+        let depth = self.stepper.cpu().stack().len();
+        let done = match self.run? {
+            Run::Continue => false,
+            Run::StepOver { depth: call_depth } => depth <= call_depth,
+            Run::StepOut { depth: call_depth } => depth < call_depth,
+        };
+
+        done.then(|| {
+            self.run = None;
+            Stop::new("step")
+        })
+    }
+
+    // Execute the instructions until the end of the frame, or until the program is stopped.
+    fn run_frame(&mut self) -> Option<Stop> {
+        let frame = self.stepper.frames();
+
+        while self.run.is_some() && self.stepper.frames() == frame {
+            let cpu = self.stepper.cpu();
+            let pc = cpu.pc();
+
+            if self.instruction_breakpoints.contains(&pc) || self.source_breakpoints.contains(&pc) {
+                self.run = None;
+
+                return Some(Stop::new("breakpoint"));
+            }
+
+            // Fast-forward to the next timer tick when the program cannot progress
+            if cpu.is_idle() {
+                self.stepper.end_frame();
+                break;
+            }
+
+            if let Some(stop) = self.step_watching() {
+                return Some(stop);
+            }
+
+            if let Some(stop) = self.check_run() {
+                return Some(stop);
+            }
+        }
+
+        None
+    }
+
+    // The source the program was assembled from, if known.
+    fn source(&self) -> Option<Value> {
+        let path = self.symbols.source.as_ref()?;
+        let name = path.file_name()?.to_string_lossy();
+
+        Some(json!({ "name": name, "path": path }))
+    }
+
+    // The current instruction, then the calls to the subroutines it's in, the most recent first.
+    fn stack_trace(&self) -> Value {
+        let cpu = self.stepper.cpu();
+        let addresses = std::iter::once(cpu.pc()).chain(
+            cpu.stack()
+                .iter()
+                .rev()
+                .map(|address| address.wrapping_sub(2)),
+        );
+        let frames: Vec<Value> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": self.symbols.describe(address),
+                    "instructionPointerReference": format!("0x{:03X}", address),
+                    "line": 0,
+                    "column": 0,
+                });
+
+                if let (Some(source), Some(line)) = (self.source(), self.symbols.line_at(address)) {
+                    frame["source"] = source;
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+
+                frame
+            })
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, reference: Option<u64>) -> Value {
+        let cpu = self.stepper.cpu();
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables: Vec<Value> = match reference {
+            Some(REGISTERS_REFERENCE) => {
+                let mut variables: Vec<Value> = cpu
+                    .v()
+                    .iter()
+                    .enumerate()
+                    .map(|(x, value)| variable(format!("V{:X}", x), format!("0x{:02X}", value)))
+                    .collect();
+                let mut i = variable("I".to_string(), format!("0x{:03X}", cpu.i()));
+
+                i["memoryReference"] = json!(format!("0x{:03X}", cpu.i()));
+                variables.push(i);
+                variables.push(variable("PC".to_string(), format!("0x{:03X}", cpu.pc())));
+                variables.push(variable("DT".to_string(), cpu.delay_timer().to_string()));
+                variables.push(variable("ST".to_string(), cpu.sound_timer().to_string()));
+                variables
+            }
+
+            // Every row of the display, as the client can only show text
+            Some(DISPLAY_REFERENCE) => cpu
+                .vram()
+                .chunks_exact(SCREEN_WIDTH as usize)
+                .enumerate()
+                .map(|(y, row)| {
+                    let pixels = row
+                        .iter()
+                        .map(|pixel| if *pixel == 1 { '█' } else { '·' })
+                        .collect();
+
+                    variable(format!("{:02}", y), pixels)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        json!({ "variables": variables })
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let ram = self.stepper.cpu().ram();
+        let start = memory_address(arguments)?.clamp(0, ram.len() as i64) as usize;
+        let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+        let end = (start + count).min(ram.len());
+
+        Ok(json!({
+            "address": format!("0x{:03X}", start),
+            "data": base64(&ram[start..end]),
+            "unreadableBytes": count - (end - start),
+        }))
+    }
+
+    // The client asks for a number of instructions around an address, which may be outside of the memory.
+    fn disassemble(&self, arguments: &Value) -> Result<Value, String> {
+        let cpu = self.stepper.cpu();
+        let start =
+            memory_address(arguments)? + arguments["instructionOffset"].as_i64().unwrap_or(0) * 2;
+        let count = arguments["instructionCount"].as_i64().unwrap_or(0);
+        let instructions: Vec<Value> = (0..count)
+            .map(|index| {
+                let address = start + index * 2;
+
+                if address < 0 || address >= cpu.ram().len() as i64 - 1 {
+                    return json!({
+                        "address": format!("0x{:03X}", address.max(0)),
+                        "instruction": "",
+                        "presentationHint": "invalid",
+                    });
+                }
+
+                let address = address as u16;
+                let mut instruction = json!({
+                    "address": format!("0x{:03X}", address),
+                    "instructionBytes": format!("{:04X}", cpu.instruction_at(address)),
+                    "instruction": cpu
+                        .try_peek_at(address)
+                        .map_or("???".to_string(), |operation| self.symbols.disassemble(operation)),
+                });
+
+                if let Some(label) = self.symbols.label_at(address) {
+                    instruction["symbol"] = json!(label);
+                }
+
+                if let (Some(source), Some(line)) = (self.source(), self.symbols.line_at(address)) {
+                    instruction["location"] = source;
+                    instruction["line"] = json!(line);
+                }
+
+                instruction
+            })
+            .collect();
+
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    // Evaluate a register (`V3`, `I`, `PC`, `DT`, `ST`) or a byte of memory (`[0x300]`), e.g. in a watch expression.
+    fn evaluate(&self, expression: &str) -> Result<Value, String> {
+        let cpu = self.stepper.cpu();
+
+        // A label evaluates to its address, so that the memory it refers to can be opened
+        if let Some(address) = self.symbols.labels.get(expression.trim()) {
+            return Ok(json!({
+                "result": format!("0x{:03X}", address),
+                "memoryReference": format!("0x{:03X}", address),
+                "variablesReference": 0,
+            }));
+        }
+
+        let expression = expression.trim().to_uppercase();
+        let register = |x: &str| u8::from_str_radix(x, 16).ok().filter(|x| *x < 16);
+        let (result, memory_reference) = match expression.as_str() {
+            "I" => (format!("0x{:03X}", cpu.i()), Some(cpu.i())),
+            "PC" => (format!("0x{:03X}", cpu.pc()), Some(cpu.pc())),
+            "DT" => (cpu.delay_timer().to_string(), None),
+            "ST" => (cpu.sound_timer().to_string(), None),
+            _ => match (
+                expression.strip_prefix('V').and_then(register),
+                expression
+                    .strip_prefix('[')
+                    .and_then(|address| address.strip_suffix(']'))
+                    .and_then(|address| parse_address(&address.to_lowercase()))
+                    .filter(|address| (*address as usize) < cpu.ram().len()),
+            ) {
+                (Some(x), _) => (format!("0x{:02X}", cpu.v()[x as usize]), None),
+                (_, Some(address)) => (
+                    format!("0x{:02X}", cpu.ram()[address as usize]),
+                    Some(address),
+                ),
+                _ => return Err(format!("Cannot evaluate {}", expression)),
+            },
+        };
+        let mut body = json!({ "result": result, "variablesReference": 0 });
+
+        if let Some(address) = memory_reference {
+            body["memoryReference"] = json!(format!("0x{:03X}", address));
+        }
+
+        Ok(body)
+    }
+}
+
+// The address given by the `memoryReference` and `offset` arguments of a request.
+fn memory_address(arguments: &Value) -> Result<i64, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or("");
+    let address = parse_address(reference).ok_or(format!("Invalid address {}", reference))?;
+
+    Ok(address as i64 + arguments["offset"].as_i64().unwrap_or(0))
+}
+
+// Addresses are given in hexadecimal (`0x2A6`) or in decimal.
+fn parse_address(reference: &str) -> Option<u16> {
+    match reference.strip_prefix("0x") {
+        Some(hexadecimal) => u16::from_str_radix(hexadecimal, 16).ok(),
+        None => reference.parse().ok(),
+    }
+}
+
+// Memory is sent to the client encoded in Base64.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | (*byte as u32) << (16 - index * 8)
+        });
+
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - index * 6)) as usize & 0x3F] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn base64_padding() {
+        // The test vectors of RFC 4648
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(bytes.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn base64_every_byte() {
+        assert_eq!(base64(&[0x00, 0x10, 0x83]), "ABCD");
+        assert_eq!(base64(&[0xFB, 0xFF, 0xBF]), "+/+/");
+        assert_eq!(base64(&[0xFF]), "/w==");
+    }
+
+    #[test]
+    fn read_messages() {
+        let first = r#"{"seq":1,"type":"request","command":"initialize"}"#;
+        let second =
+            r#"{"seq":2,"type":"request","command":"launch","arguments":{"program":"é.ch8"}}"#;
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+        let mut reader = Cursor::new(input);
+
+        let message = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!((message.seq, message.command.as_str()), (1, "initialize"));
+
+        // The length is in bytes, not in characters
+        let message = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(message.arguments["program"], "é.ch8");
+
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_invalid_messages() {
+        for input in [
+            "\r\n{}",
+            "Content-Length: x\r\n\r\n{}",
+            "Content-Length: 10\r\n\r\n{}",
+        ] {
+            assert!(read_message(&mut Cursor::new(input)).is_err());
+        }
+    }
+
+    #[test]
+    fn send_message() {
+        let mut adapter = Adapter {
+            writer: Vec::new(),
+            seq: 0,
+            events: Vec::new(),
+            session: None,
+            stop_on_entry: false,
+        };
+
+        adapter
+            .send(json!({ "type": "event", "event": "output", "body": { "output": "€" } }))
+            .unwrap();
+        adapter
+            .send(json!({ "type": "event", "event": "exited" }))
+            .unwrap();
+
+        let mut reader = Cursor::new(adapter.writer);
+
+        for seq in 1..=2 {
+            let message = read_message(&mut reader).unwrap().unwrap();
+
+            assert_eq!((message.seq, message.kind.as_str()), (seq, "event"));
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::{collections::HashMap, error::Error, fs, path::PathBuf};
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::{config, key_mapping::GamepadButton, palette::Color, settings::RomSettings};
+
+// The database is the `programs.json` file of the CHIP-8 database maintained by the community
+// (https://github.com/chip-8/chip-8-database): a list of programs, each with the ROMs of its versions keyed by their
+// SHA-1. It isn't bundled, so it must be downloaded to the configuration directory, where it's looked up first. Only
+// the ROMs shipped with the emulator are embedded, in the same schema.
+const EMBEDDED: &str = include_str!("../data/programs.json");
+const FILE_NAME: &str = "programs.json";
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+
+    #[serde(default)]
+    authors: Vec<String>,
+
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    // The platforms the ROM runs on, the preferred one first.
+    #[serde(default)]
+    platforms: Vec<String>,
+
+    // The number of instructions executed per frame.
+    tickrate: Option<u32>,
+
+    // The quirks that differ from the ones of a platform, by platform, e.g. `{"superchip": {"shift": false}}`.
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+
+    colors: Option<Colors>,
+
+    // The keypad values played with the directions and the action buttons, e.g. `{"up": 5, "a": 6}`.
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    // The colors indexed by the value of a pixel, as in the palettes.
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+// A ROM found in the database.
+pub struct Game {
+    pub title: String,
+    pub authors: Vec<String>,
+
+    // The settings to run the ROM with, unless given on the command line or in its settings file.
+    pub settings: RomSettings,
+
+    // The colors of the pixels, replacing the ones of the theme unless a theme is given on the command line.
+    pub colors: Vec<Color>,
+
+    // The platforms of the ROM when none of them is supported, e.g. XO-CHIP.
+    pub unsupported_platforms: Vec<String>,
+}
+
+impl Game {
+    // The title and the authors, e.g. `Tetris by Fran Dachille`.
+    pub fn name(&self) -> String {
+        match self.authors.as_slice() {
+            [] => self.title.clone(),
+            authors => format!("{} by {}", self.title, authors.join(", ")),
+        }
+    }
+}
+
+// The path the database is downloaded to in the configuration directory.
+pub fn path() -> Option<PathBuf> {
+    config::directory().map(|directory| directory.join(FILE_NAME))
+}
+
+// The path of the database downloaded to the configuration directory, when it's there.
+pub fn downloaded() -> Option<PathBuf> {
+    path().filter(|path| path.exists())
+}
+
+// Find a ROM in the database downloaded to the configuration directory, if any, or else in the embedded one.
+pub fn lookup(rom: &[u8]) -> Result<Option<Game>, Box<dyn Error>> {
+    let hash = crate::rom_hash(rom);
+
+    if let Some(path) = downloaded() {
+        let content = fs::read_to_string(&path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+        let programs: Vec<Program> = serde_json::from_str(&content)
+            .map_err(|error| format!("Invalid database in {}: {}", path.display(), error))?;
+
+        if let Some(game) = find(programs, &hash) {
+            return Ok(Some(game));
+        }
+    }
+
+    let programs: Vec<Program> = serde_json::from_str(EMBEDDED)?;
+
+    Ok(find(programs, &hash))
+}
+
+// The settings of a ROM found in the database, or the default ones.
+pub fn settings(rom: &[u8]) -> Result<RomSettings, Box<dyn Error>> {
+    Ok(lookup(rom)?.map(|game| game.settings).unwrap_or_default())
+}
+
+fn find(programs: Vec<Program>, hash: &str) -> Option<Game> {
+    programs.into_iter().find_map(|mut program| {
+        let rom = program.roms.remove(hash)?;
+        let (super_chip, unsupported_platforms) = match rom
+            .platforms
+            .iter()
+            .find_map(|platform| super_chip_quirks(platform, &rom.quirky_platforms))
+        {
+            Some(super_chip) => (Some(super_chip), Vec::new()),
+            None => (None, rom.platforms.clone()),
+        };
+        let keys = rom
+            .keys
+            .iter()
+            .filter_map(|(name, key)| Some((host_key(name)?, *key)))
+            .collect();
+        let gamepad = rom
+            .keys
+            .iter()
+            .filter_map(|(name, key)| Some((gamepad_button(name)?, *key)))
+            .collect();
+
+        Some(Game {
+            title: program.title,
+            authors: program.authors,
+            settings: RomSettings {
+                super_chip,
+                ipf: rom.tickrate.filter(|tickrate| *tickrate > 0),
+                keys,
+                gamepad,
+            },
+            colors: rom
+                .colors
+                .map(|colors| colors.pixels)
+                .unwrap_or_default()
+                .iter()
+                .map_while(|color| color.parse().ok())
+                .collect(),
+            unsupported_platforms,
+        })
+    })
+}
+
+// Tell whether a ROM needs the quirks of the SUPER-CHIP on a platform, or `None` when the platform isn't supported.
+//
+// The SUPER-CHIP quirks are the shifts ignoring VY (`shift`), the loads and stores leaving I unchanged
+// (`memoryLeaveIUnchanged`) and the jumps adding VX (`jump`). They can't be enabled separately, so they're enabled when
+// most of them are enabled on the platform, once the quirks of the ROM are applied: the CHIP-48 thus runs with them,
+// even though it incremented I by X instead of leaving it unchanged.
+fn super_chip_quirks(
+    platform: &str,
+    quirky_platforms: &HashMap<String, HashMap<String, bool>>,
+) -> Option<bool> {
+    let defaults = match platform {
+        "originalChip8" | "hybridVIP" | "modernChip8" => [false, false, false],
+        "chip48" => [true, false, true],
+        "superchip1" | "superchip" => [true, true, true],
+        _ => return None,
+    };
+    let quirks = quirky_platforms.get(platform);
+    let enabled = ["shift", "memoryLeaveIUnchanged", "jump"]
+        .iter()
+        .zip(defaults)
+        .filter(|(quirk, default)| {
+            quirks
+                .and_then(|quirks| quirks.get(**quirk))
+                .copied()
+                .unwrap_or(*default)
+        })
+        .count();
+
+    Some(enabled >= 2)
+}
+
+// The directions are bound to the arrow keys and the action buttons to the space bar and the shift key.
+fn host_key(name: &str) -> Option<VirtualKeyCode> {
+    let key = match name {
+        "up" => VirtualKeyCode::Up,
+        "down" => VirtualKeyCode::Down,
+        "left" => VirtualKeyCode::Left,
+        "right" => VirtualKeyCode::Right,
+        "a" => VirtualKeyCode::Space,
+        "b" => VirtualKeyCode::LShift,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+fn gamepad_button(name: &str) -> Option<GamepadButton> {
+    let button = match name {
+        "up" => GamepadButton::DPadUp,
+        "down" => GamepadButton::DPadDown,
+        "left" => GamepadButton::DPadLeft,
+        "right" => GamepadButton::DPadRight,
+        "a" => GamepadButton::South,
+        "b" => GamepadButton::East,
+        _ => return None,
+    };
+
+    Some(button)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quirks(platform: &str, quirks: &[(&str, bool)]) -> HashMap<String, HashMap<String, bool>> {
+        HashMap::from([(
+            platform.to_string(),
+            quirks
+                .iter()
+                .map(|(quirk, enabled)| (quirk.to_string(), *enabled))
+                .collect(),
+        )])
+    }
+
+    #[test]
+    fn super_chip_platforms() {
+        let none = HashMap::new();
+
+        assert_eq!(super_chip_quirks("originalChip8", &none), Some(false));
+        assert_eq!(super_chip_quirks("modernChip8", &none), Some(false));
+        assert_eq!(super_chip_quirks("superchip", &none), Some(true));
+        assert_eq!(super_chip_quirks("superchip1", &none), Some(true));
+
+        // Two of the three quirks are enabled on the CHIP-48
+        assert_eq!(super_chip_quirks("chip48", &none), Some(true));
+
+        assert_eq!(super_chip_quirks("xochip", &none), None);
+        assert_eq!(super_chip_quirks("megachip8", &none), None);
+    }
+
+    #[test]
+    fn super_chip_majority() {
+        // One quirk of the platform disabled by the ROM still leaves most of them
+        assert_eq!(
+            super_chip_quirks("superchip", &quirks("superchip", &[("jump", false)])),
+            Some(true)
+        );
+        assert_eq!(
+            super_chip_quirks(
+                "superchip",
+                &quirks("superchip", &[("jump", false), ("shift", false)])
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            super_chip_quirks("chip48", &quirks("chip48", &[("shift", false)])),
+            Some(false)
+        );
+        assert_eq!(
+            super_chip_quirks(
+                "chip48",
+                &quirks("chip48", &[("memoryLeaveIUnchanged", true)])
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            super_chip_quirks(
+                "originalChip8",
+                &quirks("originalChip8", &[("shift", true), ("jump", true)])
+            ),
+            Some(true)
+        );
+
+        // Other quirks don't count, nor do the quirks of other platforms
+        assert_eq!(
+            super_chip_quirks(
+                "originalChip8",
+                &quirks("originalChip8", &[("vblank", true), ("shift", true)])
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            super_chip_quirks(
+                "superchip",
+                &quirks("chip48", &[("shift", false), ("jump", false)])
+            ),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(host_key("up"), Some(VirtualKeyCode::Up));
+        assert_eq!(host_key("a"), Some(VirtualKeyCode::Space));
+        assert_eq!(host_key("b"), Some(VirtualKeyCode::LShift));
+        assert_eq!(host_key("start"), None);
+        assert_eq!(gamepad_button("left"), Some(GamepadButton::DPadLeft));
+        assert_eq!(gamepad_button("a"), Some(GamepadButton::South));
+        assert_eq!(gamepad_button("b"), Some(GamepadButton::East));
+        assert_eq!(gamepad_button("start"), None);
+    }
+
+    #[test]
+    fn find_rom() {
+        let programs = r##"[
+            {"title": "Other", "roms": {"0000": {"platforms": ["originalChip8"]}}},
+            {
+                "title": "Game",
+                "authors": ["A", "B"],
+                "roms": {
+                    "1234": {
+                        "platforms": ["xochip", "superchip"],
+                        "quirkyPlatforms": {"superchip": {"jump": false}},
+                        "tickrate": 30,
+                        "colors": {"pixels": ["#000000", "#ffffff", "invalid", "#ff0000"]},
+                        "keys": {"up": 5, "a": 6, "start": 7}
+                    },
+                    "5678": {"platforms": ["xochip"], "tickrate": 0}
+                }
+            }
+        ]"##;
+        let programs = || serde_json::from_str::<Vec<Program>>(programs).unwrap();
+
+        assert!(find(programs(), "9999").is_none());
+
+        let game = find(programs(), "1234").unwrap();
+
+        assert_eq!(game.name(), "Game by A, B");
+        assert_eq!(game.settings.super_chip, Some(true));
+        assert_eq!(game.settings.ipf, Some(30));
+        assert_eq!(
+            game.settings.keys,
+            HashMap::from([(VirtualKeyCode::Up, 5), (VirtualKeyCode::Space, 6)])
+        );
+        assert_eq!(
+            game.settings.gamepad,
+            HashMap::from([(GamepadButton::DPadUp, 5), (GamepadButton::South, 6)])
+        );
+        // The colors stop at the first invalid one
+        assert_eq!(game.colors.len(), 2);
+        assert!(game.unsupported_platforms.is_empty());
+
+        let game = find(programs(), "5678").unwrap();
+
+        assert_eq!(game.name(), "Game by A, B");
+        assert_eq!(game.settings.super_chip, None);
+        assert_eq!(game.settings.ipf, None);
+        assert_eq!(game.unsupported_platforms, ["xochip"]);
+    }
+
+    #[test]
+    fn embedded_rom() {
+        let rom = fs::read("data/chip8-test-suite.ch8").unwrap();
+        let game = find(
+            serde_json::from_str(EMBEDDED).unwrap(),
+            &crate::rom_hash(&rom),
+        )
+        .unwrap();
+
+        assert_eq!(game.name(), "CHIP-8 test suite by Timendus");
+        assert_eq!(game.settings.super_chip, Some(false));
+    }
+}
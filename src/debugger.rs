@@ -0,0 +1,731 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    io::{self, Stdout, Write},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{self, Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, ClearType},
+};
+use winit::event::ElementState;
+
+use crate::{
+    cpu::Cpu,
+    key_mapping::{HostKey, KeyMapping, Keypad},
+    palette::Palette,
+    stepper::{ReverseStop, Stepper, Watch},
+    symbols::Symbols,
+    tui::{self, RawTerminal, TerminalKeys},
+};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1000000 / 60);
+
+// The terminal is polled for key events at this interval while the program is paused.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// The panels are laid out in columns, the disassembly taking the whole height of the terminal on the left:
+//
+//     Disassembly | Registers | Stack | Display
+//                 | Memory
+const DISASSEMBLY_X: u16 = 0;
+const DISASSEMBLY_WIDTH: u16 = 32;
+const REGISTERS_X: u16 = 33;
+const REGISTERS_WIDTH: u16 = 14;
+const STACK_X: u16 = 48;
+const STACK_WIDTH: u16 = 18;
+const DISPLAY_X: u16 = 67;
+const DISPLAY_WIDTH: u16 = 32;
+const PANELS_HEIGHT: u16 = 11;
+const MEMORY_Y: u16 = PANELS_HEIGHT + 1;
+const MEMORY_ROWS: u16 = 8;
+const BYTES_PER_ROW: usize = 16;
+
+// The smallest terminal the layout fits in, with a line for the status bar.
+const MIN_WIDTH: u16 = DISPLAY_X + DISPLAY_WIDTH;
+const MIN_HEIGHT: u16 = MEMORY_Y + MEMORY_ROWS + 2;
+
+// Debug a ROM in the terminal: the program is paused from the start, and can be executed instruction by instruction,
+// until a breakpoint or until the instruction under the cursor. It can also be stepped back instruction by instruction
+// or run backwards until a breakpoint. The CPU runs on the main thread, as it's stopped most of the time and its whole
+// state is shown after every step.
+pub fn run(
+    cpu: Cpu,
+    ipf: u32,
+    idle_skip: bool,
+    key_mapping: KeyMapping,
+    palette: Palette,
+    symbols: Symbols,
+    title: String,
+) -> Result<(), Box<dyn Error>> {
+    let mut terminal = RawTerminal::enter()?;
+    let mut debugger = Debugger {
+        cursor: cpu.pc(),
+        stepper: Stepper::new(cpu, ipf),
+        idle_skip,
+        breakpoints: BTreeSet::new(),
+        watches: BTreeSet::new(),
+        prompt: None,
+        running: false,
+        run_to: None,
+        memory_start: None,
+        message: "Paused".to_string(),
+        keypad: Keypad::default(),
+        palette,
+        symbols,
+        title,
+    };
+
+    debugger.event_loop(&mut terminal, &key_mapping)
+}
+
+struct Debugger {
+    stepper: Stepper,
+    idle_skip: bool,
+
+    // Addresses at which the execution is paused, before executing the instruction.
+    breakpoints: BTreeSet<u16>,
+
+    // Registers and bytes of memory at which the execution is paused when they change.
+    watches: BTreeSet<Watch>,
+
+    // The watch being typed, after F6 is pressed.
+    prompt: Option<String>,
+    running: bool,
+
+    // A temporary breakpoint, removed when the execution is paused, set to run until the instruction under the cursor.
+    run_to: Option<u16>,
+
+    // Address of the selected instruction in the disassembly.
+    cursor: u16,
+
+    // First address of the memory dump, or `None` to follow I.
+    memory_start: Option<u16>,
+
+    // Why the program is paused, or the result of the last command, shown in the status bar.
+    message: String,
+
+    keypad: Keypad,
+    palette: Palette,
+    symbols: Symbols,
+    title: String,
+}
+
+impl Debugger {
+    fn event_loop(
+        &mut self,
+        terminal: &mut RawTerminal,
+        key_mapping: &KeyMapping,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut keys = TerminalKeys::new(terminal);
+        let mut next_frame = Instant::now();
+        let mut redraw = true;
+
+        loop {
+            if redraw {
+                self.draw(&mut terminal.stdout)?;
+                redraw = false;
+            }
+
+            let timeout = match self.running {
+                true => next_frame
+                    .saturating_duration_since(Instant::now())
+                    .min(POLL_INTERVAL),
+                false => POLL_INTERVAL,
+            };
+
+            if event::poll(timeout)? {
+                match event::read()? {
+                    Event::Key(KeyEvent { code, kind, .. })
+                        if self.prompt.is_some() && kind != KeyEventKind::Release =>
+                    {
+                        self.edit_prompt(code);
+                        redraw = true;
+                    }
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Esc, ..
+                    }) => return Ok(()),
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Char('c'),
+                        modifiers,
+                        ..
+                    }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    Event::Key(KeyEvent {
+                        code:
+                            code @ (KeyCode::F(_)
+                            | KeyCode::Up
+                            | KeyCode::Down
+                            | KeyCode::PageUp
+                            | KeyCode::PageDown
+                            | KeyCode::Home),
+                        kind,
+                        ..
+                    }) if kind != KeyEventKind::Release => {
+                        // Commands are only run on key presses, not on the releases reported by some terminals
+                        if !self.running && self.command(code) {
+                            next_frame = Instant::now();
+                        } else if self.running && code == KeyCode::F(5) {
+                            self.pause("Paused".to_string());
+                        }
+
+                        redraw = true;
+                    }
+                    Event::Key(KeyEvent { code, kind, .. }) => {
+                        if let Some((source, code, state)) = keys.key_event(code, kind) {
+                            redraw |= self.set_key(source, key_mapping.key(0, Some(code)), state);
+                        }
+                    }
+                    Event::Resize(..) => {
+                        queue!(terminal.stdout, terminal::Clear(ClearType::All))?;
+                        redraw = true;
+                    }
+                    _ => {}
+                }
+            }
+
+            for source in keys.expired() {
+                redraw |= self.set_key(source, None, ElementState::Released);
+            }
+
+            if self.running && Instant::now() >= next_frame {
+                self.run_frame(&mut terminal.stdout)?;
+                redraw = true;
+                next_frame += FRAME_DURATION;
+
+                // Don't try to catch up if we're late
+                let now = Instant::now();
+
+                if now > next_frame + FRAME_DURATION {
+                    next_frame = now;
+                }
+            }
+        }
+    }
+
+    // Run a command of the debugger while the program is paused, returning `true` if the program is resumed.
+    fn command(&mut self, code: KeyCode) -> bool {
+        let ram_end = (self.stepper.cpu().ram().len() - 2) as u16;
+
+        match code {
+            KeyCode::F(5) => {
+                self.resume(None);
+                return true;
+            }
+            KeyCode::F(10) => {
+                if self.step() {
+                    self.message = format!(
+                        "Stepped to {}",
+                        self.symbols.annotate(self.stepper.cpu().pc())
+                    );
+                }
+
+                self.cursor = self.stepper.cpu().pc();
+            }
+            KeyCode::F(7) => {
+                self.message = match self.stepper.step_back() {
+                    true => format!(
+                        "Stepped back to {}",
+                        self.symbols.annotate(self.stepper.cpu().pc())
+                    ),
+                    false => "Reached the start of the history".to_string(),
+                };
+                self.cursor = self.stepper.cpu().pc();
+            }
+            KeyCode::F(8) => {
+                let breakpoints = &self.breakpoints;
+                let stop = self
+                    .stepper
+                    .reverse_continue(|cpu| breakpoints.contains(&cpu.pc()), &self.watches);
+                let pc = self.stepper.cpu().pc();
+
+                self.message = match stop {
+                    ReverseStop::Breakpoint => {
+                        format!("Breakpoint at {}", self.symbols.annotate(pc))
+                    }
+                    ReverseStop::Watch(watch) => format!(
+                        "{} changed by the instruction at {}",
+                        watch,
+                        self.symbols.annotate(pc)
+                    ),
+                    ReverseStop::StartOfHistory => "Reached the start of the history".to_string(),
+                };
+                self.cursor = pc;
+            }
+            KeyCode::F(6) => self.prompt = Some(String::new()),
+            KeyCode::F(4) => {
+                self.resume(Some(self.cursor));
+                return true;
+            }
+            KeyCode::F(9) => {
+                if self.breakpoints.remove(&self.cursor) {
+                    self.message = format!(
+                        "Breakpoint removed at {}",
+                        self.symbols.annotate(self.cursor)
+                    );
+                } else {
+                    self.breakpoints.insert(self.cursor);
+                    self.message =
+                        format!("Breakpoint set at {}", self.symbols.annotate(self.cursor));
+                }
+            }
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => self.cursor = (self.cursor + 2).min(ram_end),
+            KeyCode::PageUp | KeyCode::PageDown => {
+                let page = (MEMORY_ROWS as usize * BYTES_PER_ROW) as u16;
+                let start = self.memory_start();
+
+                self.memory_start = Some(match code {
+                    KeyCode::PageUp => start.saturating_sub(page),
+                    _ => (start + page).min(self.stepper.cpu().ram().len() as u16 - page),
+                });
+            }
+            KeyCode::Home => {
+                self.cursor = self.stepper.cpu().pc();
+                self.memory_start = None;
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    // Resume the execution, until the given address if any. The instruction at the current address is always executed,
+    // so that the program can be resumed from a breakpoint.
+    fn resume(&mut self, run_to: Option<u16>) {
+        if self.step() {
+            self.running = true;
+            self.run_to = run_to;
+            self.message = match run_to {
+                Some(address) => format!("Running to {}", self.symbols.annotate(address)),
+                None => "Running".to_string(),
+            };
+        }
+    }
+
+    fn pause(&mut self, message: String) {
+        self.running = false;
+        self.run_to = None;
+        self.cursor = self.stepper.cpu().pc();
+        self.message = message;
+    }
+
+    // Execute the instructions until the end of the frame, or until a breakpoint is reached.
+    fn run_frame(&mut self, stdout: &mut Stdout) -> io::Result<()> {
+        let frame = self.stepper.frames();
+        let sound = self.stepper.sound();
+
+        while self.running && self.stepper.frames() == frame {
+            let pc = self.stepper.cpu().pc();
+
+            if self.breakpoints.contains(&pc) {
+                self.pause(format!("Breakpoint at {}", self.symbols.annotate(pc)));
+            } else if self.run_to == Some(pc) {
+                self.pause(format!("Reached {}", self.symbols.annotate(pc)));
+            } else if self.idle_skip && self.stepper.cpu().is_idle() {
+                // Fast-forward to the next timer tick (or key press) when the program cannot progress
+                self.stepper.end_frame();
+                break;
+            } else if !self.step() {
+                break;
+            }
+        }
+
+        // The bell is only rung when the sound starts
+        if self.stepper.sound() && !sound {
+            queue!(stdout, Print('\x07'))?;
+        }
+
+        Ok(())
+    }
+
+    // Execute one instruction. The program is paused instead of executing an instruction that would make the CPU
+    // panic, e.g. an unsupported one, or after an instruction changing a watch, returning `false`.
+    fn step(&mut self) -> bool {
+        let pc = self.stepper.cpu().pc();
+
+        match self.stepper.step_watching(&self.watches) {
+            Ok(None) => true,
+            Ok(Some(watch)) => {
+                self.pause(format!(
+                    "{} changed by the instruction at {}",
+                    watch,
+                    self.symbols.annotate(pc)
+                ));
+                false
+            }
+            Err(message) => {
+                self.pause(message);
+                false
+            }
+        }
+    }
+
+    // Type a watch, which is added on Enter, or removed if it's already set.
+    fn edit_prompt(&mut self, code: KeyCode) {
+        let text = match &mut self.prompt {
+            Some(text) => text,
+            None => return,
+        };
+
+        match code {
+            KeyCode::Char(character) => text.push(character),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let text = self.prompt.take().unwrap_or_default();
+                let labels = &self.symbols.labels;
+
+                // Addresses are in hexadecimal, as everywhere in the debugger
+                let watch = Watch::parse(&text, |address| {
+                    labels
+                        .get(address)
+                        .copied()
+                        .or_else(|| u16::from_str_radix(address.trim_start_matches("0x"), 16).ok())
+                });
+
+                self.message = match watch {
+                    Some(watch) if self.watches.remove(&watch) => {
+                        format!("Watch removed on {}", watch)
+                    }
+                    Some(watch) => {
+                        self.watches.insert(watch);
+                        format!("Watching {}", watch)
+                    }
+                    None => format!(
+                        "Invalid watch `{}`, expected V0 to VF, an address or a label",
+                        text
+                    ),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    // Press or release a key of the keypad, returning `true` if its state changed.
+    fn set_key(&mut self, source: HostKey, key: Option<u8>, state: ElementState) -> bool {
+        match (state, key) {
+            (ElementState::Pressed, Some(key)) if self.keypad.press(source, key) => {
+                self.stepper.press_key(key)
+            }
+            (ElementState::Released, _) => match self.keypad.release(source) {
+                Some(key) => self.stepper.release_key(key),
+                None => return false,
+            },
+            _ => return false,
+        }
+
+        true
+    }
+
+    // The memory dump follows I, with a couple of rows before it, unless it has been scrolled.
+    fn memory_start(&self) -> u16 {
+        let page = MEMORY_ROWS as usize * BYTES_PER_ROW;
+        let start = match self.memory_start {
+            Some(start) => start as usize,
+            None => {
+                (self.stepper.cpu().i() as usize / BYTES_PER_ROW).saturating_sub(2) * BYTES_PER_ROW
+            }
+        };
+
+        start.min(self.stepper.cpu().ram().len() - page) as u16
+    }
+
+    fn draw(&self, stdout: &mut Stdout) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            let text = format!("The terminal must be at least {}x{}", MIN_WIDTH, MIN_HEIGHT);
+
+            queue!(
+                stdout,
+                terminal::Clear(ClearType::All),
+                cursor::MoveTo(0, 0),
+                Print(text)
+            )?;
+
+            return stdout.flush();
+        }
+
+        self.draw_disassembly(stdout, height - 2)?;
+        self.draw_registers(stdout)?;
+        self.draw_stack(stdout)?;
+        self.draw_display(stdout)?;
+        self.draw_memory(stdout)?;
+
+        let state = match self.running {
+            true => "RUNNING",
+            false => "PAUSED",
+        };
+        let status = match &self.prompt {
+            Some(text) => format!(
+                " {} | Watch V0 to VF, an address or a label (again to remove it), then Enter: {}_",
+                self.title, text
+            ),
+            None => format!(
+                " {} | {} | {} | F5: continue/pause  F10: step  F7: step back  F8: reverse continue  F4: run to cursor  F9: breakpoint  F6: watch  Esc: quit",
+                self.title, state, self.message
+            ),
+        };
+
+        queue!(stdout, SetAttribute(Attribute::Reverse))?;
+        print_at(stdout, 0, height - 1, &status, width)?;
+        queue!(stdout, SetAttribute(Attribute::Reset))?;
+
+        stdout.flush()
+    }
+
+    // The instructions around the cursor, marking the breakpoints with `*` and the current instruction with `>`, and
+    // preceded by their labels if any. Data is disassembled too, as there is no way to tell it apart from the code.
+    fn draw_disassembly(&self, stdout: &mut Stdout, lines: u16) -> io::Result<()> {
+        let cpu = self.stepper.cpu();
+        let ram_end = cpu.ram().len() as u16 - 2;
+        let lines = lines as usize;
+        let mut rows = Vec::new();
+        let mut address = self.cursor.saturating_sub(lines as u16 / 2 * 2);
+
+        while rows.len() < lines * 2 && address <= ram_end {
+            if let Some(label) = self.symbols.label_at(address) {
+                rows.push((address, Some(label)));
+            }
+
+            rows.push((address, None));
+            address += 2;
+        }
+
+        // The labels may push the cursor down, in which case the first rows are dropped to keep it in the middle
+        let cursor_row = rows
+            .iter()
+            .position(|row| *row == (self.cursor, None))
+            .unwrap_or(0);
+        let rows = rows.iter().skip(cursor_row.saturating_sub(lines / 2));
+
+        header(stdout, DISASSEMBLY_X, 0, "Disassembly", DISASSEMBLY_WIDTH)?;
+
+        for (line, row) in rows
+            .map(Some)
+            .chain(std::iter::repeat(None))
+            .take(lines)
+            .enumerate()
+        {
+            let line = line as u16 + 1;
+            let (address, label) = match row {
+                Some(row) => *row,
+                None => {
+                    print_at(stdout, DISASSEMBLY_X, line, "", DISASSEMBLY_WIDTH)?;
+                    continue;
+                }
+            };
+
+            if let Some(label) = label {
+                queue!(stdout, SetAttribute(Attribute::Bold))?;
+                print_at(
+                    stdout,
+                    DISASSEMBLY_X,
+                    line,
+                    &format!("{}:", label),
+                    DISASSEMBLY_WIDTH,
+                )?;
+                queue!(stdout, SetAttribute(Attribute::Reset))?;
+                continue;
+            }
+
+            let text = format!(
+                "{}{} {:03X}  {:04X}  {}",
+                if self.breakpoints.contains(&address) {
+                    '*'
+                } else {
+                    ' '
+                },
+                if address == cpu.pc() { '>' } else { ' ' },
+                address,
+                cpu.instruction_at(address),
+                cpu.try_peek_at(address)
+                    .map_or("???".to_string(), |operation| self
+                        .symbols
+                        .disassemble(operation))
+            );
+
+            if address == self.cursor {
+                queue!(stdout, SetAttribute(Attribute::Reverse))?;
+            }
+
+            if self.breakpoints.contains(&address) {
+                queue!(stdout, SetForegroundColor(Color::Red))?;
+            }
+
+            print_at(stdout, DISASSEMBLY_X, line, &text, DISASSEMBLY_WIDTH)?;
+            queue!(stdout, SetAttribute(Attribute::Reset), style::ResetColor)?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_registers(&self, stdout: &mut Stdout) -> io::Result<()> {
+        let v = self.stepper.cpu().v();
+
+        header(stdout, REGISTERS_X, 0, "Registers", REGISTERS_WIDTH)?;
+
+        for row in 0..8 {
+            let text = format!(
+                "V{:X} {:02X}  V{:X} {:02X}",
+                row,
+                v[row],
+                row + 8,
+                v[row + 8]
+            );
+
+            print_at(stdout, REGISTERS_X, row as u16 + 1, &text, REGISTERS_WIDTH)?;
+        }
+
+        let lines = [
+            format!(
+                "PC {:03X}  I {:03X}",
+                self.stepper.cpu().pc(),
+                self.stepper.cpu().i()
+            ),
+            format!(
+                "DT {:02X}  ST {:02X}",
+                self.stepper.cpu().delay_timer(),
+                self.stepper.cpu().sound_timer()
+            ),
+        ];
+
+        for (row, text) in lines.iter().enumerate() {
+            print_at(stdout, REGISTERS_X, row as u16 + 9, text, REGISTERS_WIDTH)?;
+        }
+
+        Ok(())
+    }
+
+    // The return addresses, the most recent first. Only the top of a deep stack fits in the panel.
+    fn draw_stack(&self, stdout: &mut Stdout) -> io::Result<()> {
+        let stack = self.stepper.cpu().stack();
+        let lines = PANELS_HEIGHT - 1;
+
+        header(stdout, STACK_X, 0, "Stack", STACK_WIDTH)?;
+
+        for line in 0..lines {
+            let text = match stack.iter().rev().nth(line as usize) {
+                Some(_) if line == lines - 1 && stack.len() > lines as usize => "...".to_string(),
+                Some(address) => format!(
+                    "{:X} {}",
+                    stack.len() - 1 - line as usize,
+                    self.symbols.describe(*address)
+                ),
+                None => String::new(),
+            };
+
+            print_at(stdout, STACK_X, line + 1, &text, STACK_WIDTH)?;
+        }
+
+        Ok(())
+    }
+
+    // The screen drawn with the braille patterns, followed by the keys held and whether the program waits for one.
+    fn draw_display(&self, stdout: &mut Stdout) -> io::Result<()> {
+        header(stdout, DISPLAY_X, 0, "Display", DISPLAY_WIDTH)?;
+
+        queue!(
+            stdout,
+            SetForegroundColor(tui::color(&self.palette, 1)),
+            SetBackgroundColor(tui::color(&self.palette, 0))
+        )?;
+
+        let lines = tui::braille_lines(self.stepper.cpu().vram());
+
+        for (line, text) in lines.iter().enumerate() {
+            print_at(stdout, DISPLAY_X, line as u16 + 1, text, DISPLAY_WIDTH)?;
+        }
+
+        queue!(stdout, style::ResetColor)?;
+
+        let held: String = (0..16u8)
+            .filter(|key| self.keypad.is_held(*key))
+            .map(|key| format!("{:X}", key))
+            .collect();
+        let keys = format!(
+            "Keys: {}{}",
+            if held.is_empty() { "-" } else { &held },
+            if self.stepper.cpu().is_waiting_for_key() {
+                " (waiting)"
+            } else {
+                ""
+            }
+        );
+
+        print_at(
+            stdout,
+            DISPLAY_X,
+            lines.len() as u16 + 1,
+            &keys,
+            DISPLAY_WIDTH,
+        )?;
+        print_at(stdout, DISPLAY_X, lines.len() as u16 + 2, "", DISPLAY_WIDTH)
+    }
+
+    // A hexadecimal dump of the memory, highlighting the byte pointed to by I.
+    fn draw_memory(&self, stdout: &mut Stdout) -> io::Result<()> {
+        let start = self.memory_start() as usize;
+        let ram = self.stepper.cpu().ram();
+        let i = self.stepper.cpu().i() as usize;
+
+        header(
+            stdout,
+            REGISTERS_X,
+            MEMORY_Y,
+            "Memory",
+            MIN_WIDTH - REGISTERS_X,
+        )?;
+
+        for row in 0..MEMORY_ROWS {
+            let address = start + row as usize * BYTES_PER_ROW;
+
+            queue!(
+                stdout,
+                cursor::MoveTo(REGISTERS_X, MEMORY_Y + 1 + row),
+                Print(format!("{:03X} ", address))
+            )?;
+
+            for (offset, byte) in ram[address..address + BYTES_PER_ROW].iter().enumerate() {
+                queue!(stdout, Print(' '))?;
+
+                if address + offset == i {
+                    queue!(
+                        stdout,
+                        SetAttribute(Attribute::Reverse),
+                        Print(format!("{:02X}", byte)),
+                        SetAttribute(Attribute::Reset)
+                    )?;
+                } else {
+                    queue!(stdout, Print(format!("{:02X}", byte)))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn header(stdout: &mut Stdout, x: u16, y: u16, title: &str, width: u16) -> io::Result<()> {
+    queue!(stdout, SetAttribute(Attribute::Bold))?;
+    print_at(stdout, x, y, title, width)?;
+    queue!(stdout, SetAttribute(Attribute::Reset))
+}
+
+// Print a text at the given position, truncated or padded with spaces to the given width so that it replaces whatever
+// was there before without clearing the screen.
+fn print_at(stdout: &mut Stdout, x: u16, y: u16, text: &str, width: u16) -> io::Result<()> {
+    let text: String = text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(width as usize)
+        .collect();
+
+    queue!(stdout, cursor::MoveTo(x, y), Print(text))
+}
@@ -0,0 +1,148 @@
+use std::error::Error;
+
+use crate::{
+    cli::{DiffArgs, Profile},
+    cpu::Cpu,
+    SCREEN_WIDTH,
+};
+
+// Run the same ROM under two quirk profiles, with the same seed and without any key pressed, and stop at the first
+// instruction after which their states are no longer identical.
+pub fn run(args: DiffArgs, rom: Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let (left, right) = match args.profiles[..] {
+        [left, right] => (left, right),
+        _ => return Err("Exactly two profiles must be given".into()),
+    };
+    let mut cpus = [
+        Cpu::with_seed(rom.clone(), left.is_super_chip(), args.seed)?,
+        Cpu::with_seed(rom, right.is_super_chip(), args.seed)?,
+    ];
+
+    for step in 1..=args.steps {
+        let pcs = [cpus[0].pc(), cpus[1].pc()];
+
+        // An instruction that would make the CPU panic, e.g. an unsupported one, stops the comparison before it's
+        // executed. Faulting under a single profile is a divergence too.
+        match [cpus[0].fault(), cpus[1].fault()] {
+            [None, None] => {}
+            [Some(fault), Some(other)] if fault == other => {
+                println!(
+                    "No divergence before instruction {}, which both profiles fault on: {}",
+                    step, fault
+                );
+
+                return Ok(());
+            }
+            faults => {
+                println!("Diverged at instruction {}:", step);
+
+                for (profile, (cpu, fault)) in [left, right].iter().zip(cpus.iter().zip(faults)) {
+                    let description = fault.unwrap_or_else(|| cpu.peek_at(cpu.pc()).to_string());
+
+                    println!(
+                        "  {:<6} 0x{:03X}  {}",
+                        profile.to_string(),
+                        cpu.pc(),
+                        description
+                    );
+                }
+
+                return Ok(());
+            }
+        }
+
+        let operations = [cpus[0].step(), cpus[1].step()];
+
+        // The timers are decremented on a fixed instruction count rather than on the wall clock, otherwise both
+        // instances would drift apart.
+        if step % args.ipf == 0 {
+            cpus[0].decrement_timers();
+            cpus[1].decrement_timers();
+        }
+
+        if let Some(difference) = compare(&cpus[0], &cpus[1], left, right) {
+            println!("Diverged after {} instructions:", step);
+
+            for (profile, (pc, operation)) in [left, right].iter().zip(pcs.iter().zip(operations)) {
+                println!("  {:<6} 0x{:03X}  {}", profile.to_string(), pc, operation);
+            }
+
+            println!("  {}", difference);
+
+            return Ok(());
+        }
+    }
+
+    println!("No divergence after {} instructions", args.steps);
+
+    Ok(())
+}
+
+// Describe the first difference found in the registers, then in the memory and finally in the framebuffer.
+fn compare(a: &Cpu, b: &Cpu, left: Profile, right: Profile) -> Option<String> {
+    let describe = |what: String, value_a: String, value_b: String| {
+        Some(format!(
+            "{}: {} ({}) != {} ({})",
+            what, value_a, left, value_b, right
+        ))
+    };
+
+    if a.pc() != b.pc() {
+        return describe("PC".into(), hex(a.pc()), hex(b.pc()));
+    }
+
+    if a.i() != b.i() {
+        return describe("I".into(), hex(a.i()), hex(b.i()));
+    }
+
+    for (x, (value_a, value_b)) in a.v().iter().zip(b.v()).enumerate() {
+        if value_a != value_b {
+            return describe(format!("V{:X}", x), hex(*value_a), hex(*value_b));
+        }
+    }
+
+    if a.delay_timer() != b.delay_timer() {
+        return describe("DT".into(), hex(a.delay_timer()), hex(b.delay_timer()));
+    }
+
+    if a.sound_timer() != b.sound_timer() {
+        return describe("ST".into(), hex(a.sound_timer()), hex(b.sound_timer()));
+    }
+
+    if a.stack() != b.stack() {
+        return describe(
+            "Stack".into(),
+            format!("{:03X?}", a.stack()),
+            format!("{:03X?}", b.stack()),
+        );
+    }
+
+    if let Some(address) = first_difference(a.ram(), b.ram()) {
+        return describe(
+            format!("Memory at 0x{:03X}", address),
+            hex(a.ram()[address]),
+            hex(b.ram()[address]),
+        );
+    }
+
+    if let Some(position) = first_difference(a.vram(), b.vram()) {
+        let x = position % SCREEN_WIDTH as usize;
+        let y = position / SCREEN_WIDTH as usize;
+
+        return describe(
+            format!("Framebuffer pixel ({}, {})", x, y),
+            a.vram()[position].to_string(),
+            b.vram()[position].to_string(),
+        );
+    }
+
+    None
+}
+
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    a.iter().zip(b).position(|(a, b)| a != b)
+}
+
+fn hex<T: std::fmt::UpperHex>(value: T) -> String {
+    format!("0x{:02X}", value)
+}
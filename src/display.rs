@@ -0,0 +1,27 @@
+use crate::palette::Palette;
+use crate::SCREEN_WIDTH;
+
+// Convert the brightness of the pixels (from 0 for the background color to 1 for the foreground color) to the RGBA frame
+// buffer of the window, which can be wider than the screen when the on-screen keypad is visible.
+pub fn draw(brightness: &[f32], screen: &mut [u8], screen_width: usize, palette: &Palette) {
+    let rows = screen.chunks_exact_mut(screen_width * 4);
+
+    for (brightness_row, row) in brightness.chunks_exact(SCREEN_WIDTH as usize).zip(rows) {
+        for (b, pixel) in brightness_row.iter().zip(row.chunks_exact_mut(4)) {
+            pixel[..3].copy_from_slice(&mix(palette, *b)); // Red, green and blue
+            pixel[3] = 0xFF; // Alpha channel
+        }
+    }
+}
+
+fn mix(palette: &Palette, brightness: f32) -> [u8; 3] {
+    let background = palette.color(0).0;
+    let foreground = palette.color(1).0;
+    let mut color = [0; 3];
+
+    for (channel, (back, fore)) in color.iter_mut().zip(background.iter().zip(foreground)) {
+        *channel = (*back as f32 + (fore as f32 - *back as f32) * brightness).round() as u8;
+    }
+
+    color
+}
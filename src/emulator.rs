@@ -0,0 +1,476 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cheats::{Cheat, Cheats, Search, MAX_CANDIDATES_SHOWN},
+    cpu::Cpu,
+    movie::{KeyEvent, Movie},
+    profiler::Profiler,
+    recorder::Recorder,
+};
+
+// Timers should be decremented by one 60 times per second (ie. at 60 Hz). The emulator runs frame by frame at the same
+// rate, executing a fixed number of instructions per frame.
+const FRAME_DURATION: Duration = Duration::from_micros(1000000 / 60); // 60 times per second
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Messages sent by the user interface to the emulator thread.
+pub enum Input {
+    PressKey(u8),
+    ReleaseKey(u8),
+
+    // Record the screen and the sound from the next frame on, replacing the current recording if any.
+    StartRecording(Recorder),
+    StopRecording,
+
+    // Search the memory or freeze bytes, with the hotkeys of the window or the terminal.
+    Cheat(Cheat),
+
+    Quit,
+}
+
+// Messages sent by the emulator thread to the user interface.
+pub enum Output {
+    Frame(Vec<u8>),
+    Beep,
+
+    // The keys checked by the program so far (one bit per key) and whether it's waiting for any key with FX0A. Only
+    // sent when it changes.
+    KeypadUsage {
+        keys_read: u16,
+        waiting: bool,
+    },
+
+    // The number of frames emulated during the last second and the average number of instructions executed per frame.
+    // Sent every second.
+    Stats {
+        frames_per_second: f32,
+        instructions_per_frame: f32,
+    },
+
+    // A short message answering a cheat, shown on the screen, and the lines detailing it for the console (e.g. the
+    // candidates of a search and their value).
+    Message {
+        text: String,
+        details: Vec<String>,
+    },
+}
+
+pub struct Options {
+    // Number of instructions executed per frame.
+    pub ipf: u32,
+
+    // Stop executing instructions for the rest of the frame when the program is waiting in an idle loop.
+    pub idle_skip: bool,
+
+    // Profiler recording every executed instruction, and the path of the report written when the emulator stops.
+    pub profiler: Option<(Profiler, PathBuf)>,
+
+    // Movie replayed from the start. Its key events replace the ones sent by the user interface until its end.
+    pub replay: Option<Movie>,
+
+    // Movie recording the key events, and the path where it's saved when the emulator stops.
+    pub movie: Option<(Movie, PathBuf)>,
+
+    // Recorder of the screen and the sound.
+    pub recorder: Option<Recorder>,
+
+    // Values written to the memory before every frame.
+    pub cheats: Cheats,
+
+    // The ROM as loaded, identifying the cheats of the ROM when they're saved.
+    pub rom: Vec<u8>,
+}
+
+// Run the CPU on its own thread so that the user interface (window dragging, resizing, rendering) never stalls the game.
+// The emulator stops when it receives `Input::Quit` or when `output` returns `false`.
+pub fn spawn<F>(
+    mut cpu: Cpu,
+    mut options: Options,
+    inputs: Receiver<Input>,
+    mut output: F,
+) -> JoinHandle<()>
+where
+    F: FnMut(Output) -> bool + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut next_frame = Instant::now();
+        let mut keypad_usage = (0, false);
+        let mut frame = 0;
+        let mut stats = (Instant::now(), 0, 0);
+        let mut search = None;
+
+        'frames: loop {
+            let (sound, instructions) = run_frame(&mut cpu, &mut options, frame);
+
+            frame += 1;
+
+            if sound && !output(Output::Beep) {
+                break;
+            }
+
+            let (since, frames, total_instructions) = &mut stats;
+
+            *frames += 1;
+            *total_instructions += instructions;
+
+            let elapsed = since.elapsed();
+
+            if elapsed >= STATS_INTERVAL {
+                let stats_output = Output::Stats {
+                    frames_per_second: *frames as f32 / elapsed.as_secs_f32(),
+                    instructions_per_frame: *total_instructions as f32 / *frames as f32,
+                };
+
+                stats = (Instant::now(), 0, 0);
+
+                if !output(stats_output) {
+                    break;
+                }
+            }
+
+            if cpu.take_vram_changed() && !output(Output::Frame(cpu.vram().to_vec())) {
+                break;
+            }
+
+            let usage = (cpu.keys_read(), cpu.is_waiting_for_key());
+
+            if usage != keypad_usage {
+                keypad_usage = usage;
+
+                let (keys_read, waiting) = usage;
+
+                if !output(Output::KeypadUsage { keys_read, waiting }) {
+                    break;
+                }
+            }
+
+            // Wait for the next frame while still handling the inputs as soon as they arrive
+            next_frame += FRAME_DURATION;
+
+            loop {
+                let now = Instant::now();
+
+                if now >= next_frame {
+                    // Don't try to catch up if we're late, e.g. after the machine went to sleep
+                    if now - next_frame > FRAME_DURATION {
+                        next_frame = now;
+                    }
+
+                    break;
+                }
+
+                // The keys pressed by the user are ignored while a movie is replayed
+                match inputs.recv_timeout(next_frame - now) {
+                    Ok(Input::PressKey(key)) if options.replay.is_none() => {
+                        set_key(&mut cpu, &mut options.movie, frame, key, true)
+                    }
+                    Ok(Input::ReleaseKey(key)) if options.replay.is_none() => {
+                        set_key(&mut cpu, &mut options.movie, frame, key, false)
+                    }
+                    Ok(Input::PressKey(_)) | Ok(Input::ReleaseKey(_)) => {}
+                    Ok(Input::StartRecording(recorder)) => {
+                        if let Some(previous) = options.recorder.replace(recorder) {
+                            finish_recording(previous);
+                        }
+                    }
+                    Ok(Input::StopRecording) => {
+                        if let Some(recorder) = options.recorder.take() {
+                            finish_recording(recorder);
+                        }
+                    }
+                    Ok(Input::Cheat(cheat)) => {
+                        if !output(apply_cheat(&cpu, &mut options, &mut search, cheat)) {
+                            break 'frames;
+                        }
+                    }
+                    Ok(Input::Quit) | Err(RecvTimeoutError::Disconnected) => break 'frames,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+            }
+        }
+
+        finish(&cpu, options, frame);
+    })
+}
+
+// Replay a movie as fast as possible, without any user interface, e.g. to record it.
+pub fn replay(mut cpu: Cpu, mut options: Options) {
+    let frames = options.replay.as_ref().map_or(0, |movie| movie.frames);
+
+    for frame in 0..frames {
+        run_frame(&mut cpu, &mut options, frame);
+    }
+
+    finish(&cpu, options, frames);
+}
+
+// Execute the frame with the given index: apply the key events of the movie being replayed, execute the instructions,
+// decrement the timers and record the result. Return whether the sound is on during this frame, and the number of
+// instructions executed.
+fn run_frame(cpu: &mut Cpu, options: &mut Options, frame: u64) -> (bool, u32) {
+    if let Some(replay) = options.replay.take() {
+        for event in replay.events_at(frame) {
+            set_key(cpu, &mut options.movie, frame, event.key, event.pressed);
+        }
+
+        if frame < replay.frames {
+            options.replay = Some(replay);
+        } else {
+            println!("End of the movie");
+        }
+    }
+
+    options.cheats.apply(cpu);
+
+    let mut instructions = 0;
+
+    while instructions < options.ipf {
+        // Fast-forward to the next timer tick (or key press) when the program cannot progress
+        if options.idle_skip && cpu.is_idle() {
+            break;
+        }
+
+        if let Some((profiler, _)) = &mut options.profiler {
+            profiler.record(cpu);
+        }
+
+        cpu.step();
+        instructions += 1;
+    }
+
+    let sound = cpu.decrement_timers();
+
+    if let Some(recorder) = &mut options.recorder {
+        if let Err(error) = recorder.frame(cpu.vram(), sound) {
+            eprintln!("Cannot record to {}: {}", recorder.path().display(), error);
+            options.recorder = None;
+        }
+    }
+
+    (sound, instructions)
+}
+
+// Search the memory or freeze bytes as the monitor does, but while the ROM is running.
+fn apply_cheat(
+    cpu: &Cpu,
+    options: &mut Options,
+    search: &mut Option<Search>,
+    cheat: Cheat,
+) -> Output {
+    let message = |text: String, details| Output::Message { text, details };
+    let candidates = |search: &Search| -> Vec<String> {
+        match search.candidates().len() <= MAX_CANDIDATES_SHOWN {
+            true => search
+                .candidates()
+                .iter()
+                .map(|address| format!("{:03X}={:02X}", address, cpu.ram()[*address as usize]))
+                .collect(),
+            false => Vec::new(),
+        }
+    };
+
+    match (cheat, search.as_mut()) {
+        (Cheat::Search, _) => {
+            let started = search.insert(Search::new(cpu.ram()));
+
+            message(format!("Search {}", started.candidates().len()), Vec::new())
+        }
+        (Cheat::Filter(comparison), Some(search)) => {
+            search.filter(cpu.ram(), comparison);
+
+            message(
+                format!("Search {}", search.candidates().len()),
+                candidates(search),
+            )
+        }
+        // Cheats would make a movie play differently when it's replayed
+        (Cheat::Freeze, _) if options.movie.is_some() || options.replay.is_some() => {
+            message("Movie: no cheats".to_string(), Vec::new())
+        }
+        (Cheat::Freeze, Some(search)) => match search.candidates().len() {
+            0 => message("No candidates".to_string(), Vec::new()),
+            count if count > MAX_CANDIDATES_SHOWN => {
+                message(format!("{} candidates", count), Vec::new())
+            }
+            count => {
+                let details = candidates(search);
+
+                for address in search.candidates() {
+                    options
+                        .cheats
+                        .freezes
+                        .insert(*address, cpu.ram()[*address as usize]);
+                }
+
+                message(format!("Frozen {}", count), details)
+            }
+        },
+        (Cheat::Filter(_) | Cheat::Freeze, None) => message("F5: search".to_string(), Vec::new()),
+        // The cheats already saved are kept, even when they weren't applied
+        (Cheat::Save, _) => {
+            let saved = Cheats::load(&options.rom).and_then(|mut cheats| {
+                cheats.freezes.extend(&options.cheats.freezes);
+                cheats.save(&options.rom)
+            });
+
+            match saved {
+                Ok(path) => message(
+                    "Cheats saved".to_string(),
+                    vec![format!("Cheats saved to {}", path.display())],
+                ),
+                Err(error) => message("Cheats error".to_string(), vec![error.to_string()]),
+            }
+        }
+    }
+}
+
+fn set_key(
+    cpu: &mut Cpu,
+    movie: &mut Option<(Movie, PathBuf)>,
+    frame: u64,
+    key: u8,
+    pressed: bool,
+) {
+    if pressed {
+        cpu.press_key(key);
+    } else {
+        cpu.release_key(key);
+    }
+
+    if let Some((movie, _)) = movie {
+        movie.events.push(KeyEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+}
+
+fn finish_recording(recorder: Recorder) {
+    let path = recorder.path().to_path_buf();
+
+    match recorder.finish() {
+        Ok(()) => println!("Recording saved to {}", path.display()),
+        Err(error) => eprintln!("Cannot record to {}: {}", path.display(), error),
+    }
+}
+
+// Write everything recorded during the session once the given number of frames have been executed.
+fn finish(cpu: &Cpu, options: Options, frames: u64) {
+    if let Some((profiler, path)) = &options.profiler {
+        if let Err(error) = profiler.write_report(cpu, path) {
+            eprintln!("Cannot write the profile to {}: {}", path.display(), error);
+        }
+    }
+
+    if let Some((mut movie, path)) = options.movie {
+        movie.frames = frames;
+
+        match movie.save(&path) {
+            Ok(()) => println!("Movie saved to {}", path.display()),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    if let Some(recorder) = options.recorder {
+        finish_recording(recorder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::cheats::Comparison;
+
+    fn options() -> Options {
+        Options {
+            ipf: 1,
+            idle_skip: false,
+            profiler: None,
+            replay: None,
+            movie: None,
+            recorder: None,
+            cheats: Cheats::default(),
+            rom: Vec::new(),
+        }
+    }
+
+    fn text(output: Output) -> String {
+        match output {
+            Output::Message { text, .. } => text,
+            _ => panic!("expected a message"),
+        }
+    }
+
+    #[test]
+    fn freeze_candidates() {
+        let mut cpu = Cpu::new(vec![0x12, 0x00], false).unwrap();
+        let mut options = options();
+        let mut search = None;
+
+        for (cheat, expected) in [
+            (Cheat::Freeze, "F5: search"),
+            (Cheat::Search, "Search 4096"),
+            (Cheat::Freeze, "4096 candidates"),
+        ] {
+            assert_eq!(
+                text(apply_cheat(&cpu, &mut options, &mut search, cheat)),
+                expected
+            );
+        }
+
+        cpu.write(0x300, 1);
+        cpu.write(0x301, 2);
+
+        let increased = Cheat::Filter(Comparison::Increased);
+
+        assert_eq!(
+            text(apply_cheat(&cpu, &mut options, &mut search, increased)),
+            "Search 2"
+        );
+
+        cpu.write(0x301, 1);
+
+        match apply_cheat(
+            &cpu,
+            &mut options,
+            &mut search,
+            Cheat::Filter(Comparison::Decreased),
+        ) {
+            Output::Message { text, details } => {
+                assert_eq!(text, "Search 1");
+                assert_eq!(details, ["301=01"]);
+            }
+            _ => panic!("expected a message"),
+        }
+
+        assert_eq!(
+            text(apply_cheat(&cpu, &mut options, &mut search, Cheat::Freeze)),
+            "Frozen 1"
+        );
+        assert_eq!(options.cheats.freezes, BTreeMap::from([(0x301, 1)]));
+    }
+
+    #[test]
+    fn no_cheats_in_movies() {
+        let cpu = Cpu::new(vec![0x12, 0x00], false).unwrap();
+        let mut options = options();
+        let mut search = Some(Search::new(&cpu.ram()[..1]));
+
+        options.movie = Some((Movie::new(0, false, 1, false), PathBuf::new()));
+
+        assert_eq!(
+            text(apply_cheat(&cpu, &mut options, &mut search, Cheat::Freeze)),
+            "Movie: no cheats"
+        );
+        assert!(options.cheats.is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use gilrs::{Button, EventType, Gilrs};
+use winit::event::ElementState;
+
+use crate::key_mapping::{GamepadButton, HostKey};
+
+// Gamepads have to be polled, so the event loop cannot sleep for longer than this.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(8);
+
+// The D-pad is mapped like the arrows of a numeric keypad, which many games use for movement, and the South button
+// to the key in the middle of them.
+const DEFAULT_BINDINGS: [(GamepadButton, u8); 5] = [
+    (GamepadButton::DPadUp, 0x02),
+    (GamepadButton::DPadLeft, 0x04),
+    (GamepadButton::DPadRight, 0x06),
+    (GamepadButton::DPadDown, 0x08),
+    (GamepadButton::South, 0x05),
+];
+
+const BUTTONS: [(Button, GamepadButton); 16] = [
+    (Button::South, GamepadButton::South),
+    (Button::East, GamepadButton::East),
+    (Button::North, GamepadButton::North),
+    (Button::West, GamepadButton::West),
+    (Button::LeftTrigger, GamepadButton::LeftTrigger),
+    (Button::LeftTrigger2, GamepadButton::LeftTrigger2),
+    (Button::RightTrigger, GamepadButton::RightTrigger),
+    (Button::RightTrigger2, GamepadButton::RightTrigger2),
+    (Button::Select, GamepadButton::Select),
+    (Button::Start, GamepadButton::Start),
+    (Button::LeftThumb, GamepadButton::LeftThumb),
+    (Button::RightThumb, GamepadButton::RightThumb),
+    (Button::DPadUp, GamepadButton::DPadUp),
+    (Button::DPadDown, GamepadButton::DPadDown),
+    (Button::DPadLeft, GamepadButton::DPadLeft),
+    (Button::DPadRight, GamepadButton::DPadRight),
+];
+
+pub struct Gamepads {
+    gilrs: Gilrs,
+    bindings: HashMap<GamepadButton, u8>,
+}
+
+impl Gamepads {
+    // Use the default bindings unless some are given.
+    pub fn new(bindings: &HashMap<GamepadButton, u8>) -> Result<Gamepads, Box<dyn Error>> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(gilrs::Error::NotImplemented(gilrs)) => {
+                eprintln!("Gamepads are not supported on this platform");
+                gilrs
+            }
+            Err(error) => return Err(format!("Cannot initialize gamepads: {}", error).into()),
+        };
+        let bindings = match bindings.is_empty() {
+            true => DEFAULT_BINDINGS.into_iter().collect(),
+            false => bindings.clone(),
+        };
+
+        for (_, gamepad) in gilrs.gamepads() {
+            println!("Gamepad connected: {}", gamepad.name());
+        }
+
+        Ok(Gamepads { gilrs, bindings })
+    }
+
+    // Handle the pending gamepad events, calling `handler` with the source, the bound keypad value (if any) and the
+    // state of every button event.
+    pub fn poll<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(HostKey, Option<u8>, ElementState),
+    {
+        while let Some(event) = self.gilrs.next_event() {
+            let id: usize = event.id.into();
+
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = convert(button) {
+                        let key = self.bindings.get(&button).copied();
+
+                        handler(HostKey::Button(id, button), key, ElementState::Pressed);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = convert(button) {
+                        handler(HostKey::Button(id, button), None, ElementState::Released);
+                    }
+                }
+                EventType::Connected => {
+                    println!("Gamepad connected: {}", self.gilrs.gamepad(event.id).name());
+                }
+                // Release every button of an unplugged gamepad so that no key stays stuck
+                EventType::Disconnected => {
+                    println!(
+                        "Gamepad disconnected: {}",
+                        self.gilrs.gamepad(event.id).name()
+                    );
+
+                    for (_, button) in BUTTONS {
+                        handler(HostKey::Button(id, button), None, ElementState::Released);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn convert(button: Button) -> Option<GamepadButton> {
+    BUTTONS
+        .iter()
+        .find(|(gilrs_button, _)| *gilrs_button == button)
+        .map(|(_, button)| *button)
+}
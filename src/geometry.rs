@@ -0,0 +1,43 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+const GEOMETRY_FILE_NAME: &str = "window.toml";
+
+// The position and the size of the window (in physical pixels) are saved when the emulator exits and restored the next
+// time it's started.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl WindowGeometry {
+    // A missing or invalid file is ignored: the window is then opened with the default size.
+    pub fn load() -> Option<WindowGeometry> {
+        let content = fs::read_to_string(path()?).ok()?;
+
+        toml::from_str(&content).ok()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = path().ok_or("Cannot find the configuration directory")?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        fs::write(&path, toml::to_string(self)?)
+            .map_err(|error| format!("Cannot write {}: {}", path.display(), error).into())
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    config::directory().map(|directory| directory.join(GEOMETRY_FILE_NAME))
+}
@@ -1,20 +1,283 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    hash::Hash,
+};
+
+use clap::ValueEnum;
+use serde::{
+    de::{value, DeserializeOwned, Error, IntoDeserializer},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use winit::event::VirtualKeyCode;
 
-pub const KEY_MAPPING: [(VirtualKeyCode, u8); 16] = [
-    (VirtualKeyCode::Key1, 0x01),
-    (VirtualKeyCode::Key2, 0x02),
-    (VirtualKeyCode::Key3, 0x03),
-    (VirtualKeyCode::Key4, 0x0C),
-    (VirtualKeyCode::A, 0x04),
-    (VirtualKeyCode::Z, 0x05),
-    (VirtualKeyCode::E, 0x06),
-    (VirtualKeyCode::R, 0x0D),
-    (VirtualKeyCode::Q, 0x07),
-    (VirtualKeyCode::S, 0x08),
-    (VirtualKeyCode::D, 0x09),
-    (VirtualKeyCode::F, 0x0E),
-    (VirtualKeyCode::W, 0x0A),
-    (VirtualKeyCode::X, 0x00),
-    (VirtualKeyCode::C, 0x0B),
-    (VirtualKeyCode::V, 0x0F),
+// The original COSMAC VIP had a 16-key hexadecimal keypad with the following layout:
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+//
+// It's mapped to the 4x4 block of keys found on the left of a keyboard, whose labels depend on the keyboard layout.
+pub const KEYPAD: [u8; 16] = [
+    0x01, 0x02, 0x03, 0x0C, //
+    0x04, 0x05, 0x06, 0x0D, //
+    0x07, 0x08, 0x09, 0x0E, //
+    0x0A, 0x00, 0x0B, 0x0F, //
+];
+
+const QWERTY: [VirtualKeyCode; 16] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::W,
+    VirtualKeyCode::E,
+    VirtualKeyCode::R,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::F,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::X,
+    VirtualKeyCode::C,
+    VirtualKeyCode::V,
+];
+
+const AZERTY: [VirtualKeyCode; 16] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::A,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::E,
+    VirtualKeyCode::R,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::F,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::C,
+    VirtualKeyCode::V,
+];
+
+const QWERTZ: [VirtualKeyCode; 16] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::W,
+    VirtualKeyCode::E,
+    VirtualKeyCode::R,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::F,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::X,
+    VirtualKeyCode::C,
+    VirtualKeyCode::V,
+];
+
+const DVORAK: [VirtualKeyCode; 16] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Apostrophe,
+    VirtualKeyCode::Comma,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::P,
+    VirtualKeyCode::A,
+    VirtualKeyCode::O,
+    VirtualKeyCode::E,
+    VirtualKeyCode::U,
+    VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
 ];
+
+// The scancodes of the same 4x4 block identify the physical position of the keys whatever the layout configured in the
+// operating system. These are the scancodes reported on Windows and Linux.
+const SCANCODES: [u32; 16] = [
+    0x02, 0x03, 0x04, 0x05, // 1 2 3 4
+    0x10, 0x11, 0x12, 0x13, // Q W E R
+    0x1E, 0x1F, 0x20, 0x21, // A S D F
+    0x2C, 0x2D, 0x2E, 0x2F, // Z X C V
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+}
+
+// The buttons of a gamepad, named after their position as the layout of the face buttons differs between vendors.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HostKey {
+    Virtual(VirtualKeyCode),
+    Scancode(u32),
+
+    // A click on the on-screen keypad.
+    Mouse,
+
+    // A button of the gamepad with the given identifier.
+    #[cfg(feature = "gamepad")]
+    Button(usize, GamepadButton),
+}
+
+pub struct KeyMapping {
+    bindings: HashMap<HostKey, u8>,
+}
+
+impl KeyMapping {
+    pub fn from_layout(layout: Layout) -> KeyMapping {
+        let keys = match layout {
+            Layout::Qwerty => QWERTY,
+            Layout::Azerty => AZERTY,
+            Layout::Qwertz => QWERTZ,
+            Layout::Dvorak => DVORAK,
+        };
+
+        KeyMapping::from_bindings(keys.into_iter().map(HostKey::Virtual).zip(KEYPAD))
+    }
+
+    pub fn physical() -> KeyMapping {
+        KeyMapping::from_bindings(SCANCODES.into_iter().map(HostKey::Scancode).zip(KEYPAD))
+    }
+
+    pub fn from_bindings(bindings: impl IntoIterator<Item = (HostKey, u8)>) -> KeyMapping {
+        KeyMapping {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+
+    // Add bindings on top of the existing ones, replacing those using the same host keys.
+    pub fn bind(&mut self, bindings: impl IntoIterator<Item = (HostKey, u8)>) {
+        self.bindings.extend(bindings);
+    }
+
+    // Find the keypad key bound to a host key, looking at its virtual key code first and then at its scancode.
+    pub fn key(&self, scancode: u32, virtual_keycode: Option<VirtualKeyCode>) -> Option<u8> {
+        virtual_keycode
+            .and_then(|code| self.bindings.get(&HostKey::Virtual(code)))
+            .or_else(|| self.bindings.get(&HostKey::Scancode(scancode)))
+            .copied()
+    }
+}
+
+// Several host keys can be bound to the same keypad key. That key is pressed when the first of them is pressed and
+// released when the last of them is released.
+#[derive(Default)]
+pub struct Keypad {
+    held: HashMap<HostKey, u8>,
+}
+
+impl Keypad {
+    // Return `true` if the keypad key wasn't already held by another host key (or by the same one, repeating).
+    pub fn press(&mut self, source: HostKey, key: u8) -> bool {
+        let newly_pressed = !self.is_held(key);
+
+        self.held.insert(source, key);
+
+        newly_pressed
+    }
+
+    // Return the keypad key released, if no other host key is still holding it.
+    pub fn release(&mut self, source: HostKey) -> Option<u8> {
+        self.held.remove(&source).filter(|key| !self.is_held(*key))
+    }
+
+    pub fn is_held(&self, key: u8) -> bool {
+        self.held.values().any(|held| *held == key)
+    }
+}
+
+// Find a key or a button by the name of its variant.
+fn parse_name<T: DeserializeOwned>(name: &str) -> Option<T> {
+    T::deserialize(name.into_deserializer())
+        .map_err(|_: value::Error| ())
+        .ok()
+}
+
+// Parse a `KEY=VALUE` binding, where KEY is a key or a button name and VALUE an hexadecimal keypad value (e.g. `Up=5`
+// or `DPadUp=5`).
+pub fn parse_binding<T: DeserializeOwned>(binding: &str) -> Result<(T, u8), String> {
+    let (name, value) = binding
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, found `{}`", binding))?;
+    let code = parse_name(name.trim()).ok_or_else(|| format!("unknown key `{}`", name))?;
+    let value = value.trim();
+    let key = u8::from_str_radix(value.trim_start_matches("0x"), 16)
+        .ok()
+        .filter(|key| *key <= 0x0F)
+        .ok_or_else(|| format!("invalid keypad value `{}`", value))?;
+
+    Ok((code, key))
+}
+
+// Deserialize a table of bindings from key names (as in `VirtualKeyCode`, e.g. `Up` or `Key5`) or button names (as in
+// `GamepadButton`, e.g. `DPadUp`) to keypad values.
+pub fn deserialize_keys<'de, D, T>(deserializer: D) -> Result<HashMap<T, u8>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Eq + Hash,
+{
+    HashMap::<String, u8>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, key)| {
+            let code = parse_name(&name)
+                .ok_or_else(|| D::Error::custom(format!("unknown key `{}`", name)))?;
+
+            match key {
+                0x00..=0x0F => Ok((code, key)),
+                _ => Err(D::Error::custom(format!(
+                    "invalid keypad value {} for `{}`",
+                    key, name
+                ))),
+            }
+        })
+        .collect()
+}
+
+// Serialize a table of bindings, sorted by key or button name.
+pub fn serialize_keys<S, T>(keys: &HashMap<T, u8>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Debug,
+{
+    let keys: BTreeMap<String, u8> = keys
+        .iter()
+        .map(|(code, key)| (format!("{:?}", code), *key))
+        .collect();
+
+    serializer.collect_map(keys)
+}
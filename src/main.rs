@@ -1,20 +1,69 @@
+mod assembler;
+mod bench;
+mod cheats;
 mod cli;
+mod config;
 mod cpu;
+mod dap;
+mod database;
+mod debugger;
+mod diff;
+mod display;
+mod emulator;
 mod font;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod geometry;
 mod key_mapping;
+mod monitor;
+mod movie;
 mod nibble;
 mod operation;
+mod osd;
+mod overlay;
+mod palette;
+mod patch;
+mod persistence;
+mod platform;
+mod profiler;
+mod recorder;
+mod screenshot;
+mod settings;
+mod stepper;
+mod symbols;
+mod tui;
 
-use crate::cli::Cli;
+use crate::cheats::{Cheat, Cheats};
+use crate::cli::{Cli, Command, ReplayArgs, RunArgs};
+use crate::config::{Config, KeyboardConfig};
 use crate::cpu::Cpu;
+use crate::emulator::{Input, Output};
+use crate::geometry::WindowGeometry;
+use crate::key_mapping::{HostKey, KeyMapping, Keypad, Layout};
+use crate::movie::Movie;
+use crate::osd::Osd;
+use crate::overlay::KeypadOverlay;
+use crate::palette::Palette;
+use crate::persistence::{Persistence, Renderer};
+use crate::platform::Platform;
+use crate::profiler::Profiler;
+use crate::recorder::Recorder;
+use crate::settings::RomSettings;
+use crate::symbols::Symbols;
 use clap::Parser;
 use pixels::{Pixels, SurfaceTexture};
-use std::{fs::File, io::Read};
+use std::{
+    error::Error,
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::mpsc::{self, Sender},
+};
 use winit::{
-    dpi::LogicalSize,
-    event::{Event, VirtualKeyCode, WindowEvent},
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Fullscreen, WindowBuilder},
 };
 use winit_input_helper::WinitInputHelper;
 
@@ -23,83 +72,685 @@ use winit_input_helper::WinitInputHelper;
 pub const SCREEN_WIDTH: u8 = 64;
 pub const SCREEN_HEIGHT: u8 = 32;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::parse();
-    let mut file = File::open(&args.rom_path)?;
+pub const DEFAULT_IPF: u32 = 10;
+
+const DEFAULT_SCALE: u32 = 10;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Run(args) => run(args, Config::load(cli.config.as_deref())?),
+        Command::Diff(args) => {
+            let rom = load_rom(&args.rom_path)?;
+
+            diff::run(args, rom)
+        }
+        Command::Bench(args) => {
+            let rom = load_rom(&args.rom_path)?;
+
+            bench::run(args, rom)
+        }
+        Command::Screenshot(args) => {
+            let rom = load_rom(&args.rom_path)?;
+
+            screenshot::run(args, Config::load(cli.config.as_deref())?, rom)
+        }
+        Command::Replay(args) => replay(args, Config::load(cli.config.as_deref())?),
+        Command::Dap(args) => dap::run(args),
+        Command::Assemble(args) => assembler::run(args),
+        Command::Monitor(args) => monitor::run(args),
+        Command::Patch(args) => patch::run(args),
+    }
+}
+
+fn load_rom(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut file = File::open(path)?;
     let mut rom = Vec::new();
 
     file.read_to_end(&mut rom)?;
 
-    let mut cpu = Cpu::new(rom, args.super_chip);
-    let file_name = args.rom_path.file_name().unwrap().to_str().unwrap();
-    let event_loop = EventLoop::new();
+    Ok(rom)
+}
+
+// The SHA-1 of a ROM in hexadecimal, which identifies it whatever the name of its file.
+fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+fn run(args: RunArgs, config: Config) -> Result<(), Box<dyn Error>> {
+    let mut rom = load_rom(&args.rom_path)?;
+
+    for path in &args.patches {
+        rom = patch::apply(&rom, path)?;
+    }
+
+    let mut settings = RomSettings::load(&args.rom_path)?;
+
+    if let Some(platform) = args.platform {
+        settings.super_chip = Some(platform.is_super_chip());
+    } else if args.super_chip {
+        settings.super_chip = Some(true);
+    }
+
+    if args.ipf.is_some() {
+        settings.ipf = args.ipf;
+    }
+
+    settings.keys.extend(args.bindings.iter().copied());
+    settings
+        .gamepad
+        .extend(args.button_bindings.iter().copied());
+
+    if args.save_settings {
+        settings.save(&args.rom_path)?;
+    }
+
+    // The settings of the ROM in the database are used when they're not given on the command line or in the file
+    let game = match args.no_database {
+        true => None,
+        false => database::lookup(&rom)?,
+    };
+
+    // Without the downloaded database, most ROMs aren't found, so a hint is shown on a single line
+    if !args.no_database && game.is_none() && database::downloaded().is_none() {
+        eprintln!(
+            "ROM not in the database, download \
+            https://github.com/chip-8/chip-8-database/raw/master/database/programs.json to {} to look it up",
+            database::path().map_or("the configuration directory".to_string(), |path| path.display().to_string())
+        );
+    }
+
+    if let Some(game) = &game {
+        settings = settings.or(game.settings.clone());
+
+        if !game.unsupported_platforms.is_empty() {
+            eprintln!(
+                "{} runs on {}, which isn't supported",
+                game.name(),
+                game.unsupported_platforms.join(", ")
+            );
+        }
+    }
+
+    let mut key_mapping = key_mapping(&args, &config.keyboard);
+    let mut keypad = Keypad::default();
+
+    key_mapping.bind(
+        settings
+            .keys
+            .iter()
+            .map(|(code, key)| (HostKey::Virtual(*code), *key)),
+    );
+
+    let rom_colors = game.as_ref().map_or(&[][..], |game| &game.colors);
+    let (mut theme, mut palette) = palette::resolve(&args.palette, &config.display, rom_colors);
+
+    let decay_rate = match config.display.decay_rate {
+        Some(rate) if rate <= 0.0 || rate > 1.0 => {
+            return Err(format!("Invalid decay rate {} in the configuration", rate).into())
+        }
+        rate => args
+            .decay_rate
+            .or(rate)
+            .unwrap_or(persistence::DEFAULT_DECAY_RATE),
+    };
+    let mut renderer = Renderer::new(
+        args.persistence
+            .or(config.display.persistence)
+            .unwrap_or(Persistence::None),
+        decay_rate,
+        SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize,
+    );
+
+    // A movie is replayed with the settings it was recorded with
+    let replay = args.replay.as_deref().map(Movie::load).transpose()?;
+    let super_chip = match (&replay, settings.super_chip) {
+        (Some(movie), _) => movie.super_chip,
+        (None, Some(super_chip)) => super_chip,
+        (None, None) => {
+            let detection = platform::detect(&rom);
+
+            if args.verbose {
+                print!("{}", detection.report());
+            } else if detection.platform == Platform::XoChip {
+                eprintln!("The ROM seems to be written for the XO-CHIP, which isn't supported");
+            }
+
+            detection.super_chip()
+        }
+    };
+    let (cpu, ipf) = match &replay {
+        Some(movie) => (
+            Cpu::with_seed(rom.clone(), movie.super_chip, movie.seed)?,
+            movie.ipf,
+        ),
+        None => (
+            Cpu::new(rom.clone(), super_chip)?,
+            settings.ipf.unwrap_or(DEFAULT_IPF),
+        ),
+    };
+
+    // Cheats would make a movie play differently when it's replayed
+    let cheats = match args.no_cheats || args.replay.is_some() || args.record_movie.is_some() {
+        true => Cheats::default(),
+        false => Cheats::load(&rom)?,
+    };
+
+    if !cheats.is_empty() {
+        println!("Cheats applied");
+    }
+
+    let recorder = args
+        .record
+        .as_deref()
+        .map(|path| Recorder::create(path, &palette, args.record_scale))
+        .transpose()?;
+    let mut recording = recorder.is_some();
+    let profiler = match &args.profile {
+        Some(path) => Some((
+            Profiler::new(rom.len(), Symbols::load(&args.rom_path)?),
+            path.clone(),
+        )),
+        None => None,
+    };
+    let idle_skip = replay
+        .as_ref()
+        .map_or(!args.no_idle_skip, |movie| movie.idle_skip);
+    let options = emulator::Options {
+        ipf,
+        idle_skip,
+        profiler,
+        movie: args
+            .record_movie
+            .clone()
+            .map(|path| (Movie::new(cpu.seed(), super_chip, ipf, idle_skip), path)),
+        replay,
+        recorder,
+        cheats,
+        rom: rom.clone(),
+    };
+    let name = match &game {
+        Some(game) => game.name(),
+        None => args
+            .rom_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    if args.tui || args.debug {
+        let interpreter = if super_chip { "SUPER-CHIP" } else { "CHIP-8" };
+        let title = format!("{} - {}", name, interpreter);
+
+        if args.debug {
+            let symbols = Symbols::load(&args.rom_path)?;
+
+            return debugger::run(
+                cpu,
+                ipf,
+                options.idle_skip,
+                key_mapping,
+                palette,
+                symbols,
+                title,
+            );
+        }
+
+        return tui::run(cpu, options, key_mapping, palette, args.tui_glyphs, title);
+    }
+
+    let event_loop = EventLoop::<Output>::with_user_event();
     let mut input = WinitInputHelper::new();
+    let scale = match args.scale.or(config.window.scale) {
+        Some(0) => return Err("Invalid scale 0 in the configuration".into()),
+        scale => scale,
+    };
     let window = {
-        let size = LogicalSize::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        let min_size = LogicalSize::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        let mut builder = WindowBuilder::new()
+            .with_title(format!("{} - CHIP-8", name))
+            .with_min_inner_size(min_size);
 
-        WindowBuilder::new()
-            .with_title([&file_name, "CHIP-8"].join(" - "))
-            .with_min_inner_size(size)
-            .build(&event_loop)
-            .unwrap()
+        let geometry = WindowGeometry::load();
+
+        // The window is reopened where it was last closed
+        if let Some(geometry) = geometry {
+            builder = builder.with_position(PhysicalPosition::new(geometry.x, geometry.y));
+        }
+
+        // An explicit scale takes precedence over the size of the window when it was last closed
+        builder = match (scale, geometry) {
+            (None, Some(geometry)) => builder
+                .with_inner_size(PhysicalSize::new(geometry.width, geometry.height))
+                .with_fullscreen(geometry.fullscreen.then(|| Fullscreen::Borderless(None))),
+            (scale, _) => {
+                let scale = scale.unwrap_or(DEFAULT_SCALE);
+
+                builder.with_inner_size(LogicalSize::new(
+                    SCREEN_WIDTH as u32 * scale,
+                    SCREEN_HEIGHT as u32 * scale,
+                ))
+            }
+        };
+
+        builder.build(&event_loop).unwrap()
     };
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
 
-        Pixels::new(SCREEN_WIDTH.into(), SCREEN_HEIGHT.into(), surface_texture).unwrap()
+        let mut pixels =
+            Pixels::new(SCREEN_WIDTH.into(), SCREEN_HEIGHT.into(), surface_texture).unwrap();
+
+        pixels.set_clear_color(clear_color(&palette));
+        pixels
+    };
+
+    // The emulator runs on its own thread: inputs are sent to it through a channel and it wakes the event loop up with
+    // user events whenever a new frame is ready or a beep should be played.
+    let (inputs, receiver) = mpsc::channel();
+    let proxy = event_loop.create_proxy();
+    let mut emulator = Some(emulator::spawn(cpu, options, receiver, move |output| {
+        proxy.send_event(output).is_ok()
+    }));
+    let mut vram = vec![0; SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize];
+    let mut overlay = KeypadOverlay::default();
+    let mut osd = Osd::default();
+
+    osd.show_message(match (super_chip, args.replay.is_some()) {
+        (false, false) => "CHIP-8",
+        (true, false) => "SUPER-CHIP",
+        (false, true) => "CHIP-8 REPLAY",
+        (true, true) => "SCHIP REPLAY",
+    });
+
+    if args.stats {
+        osd.toggle_stats();
+    }
+
+    // The geometry of the window is only tracked while it's not in fullscreen, to restore it when leaving fullscreen
+    let mut geometry = {
+        let position = window.outer_position().unwrap_or_default();
+        let size = window.inner_size();
+
+        WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            fullscreen: false,
+        }
     };
 
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = gamepad::Gamepads::new(&settings.gamepad)?;
+
     event_loop.run(move |event, _, control_flow| {
-        // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't dispatched any events. This is
-        // ideal for games and similar applications.
-        *control_flow = ControlFlow::Poll;
+        // The event loop only has to react to window events and to the frames produced by the emulator thread, so it
+        // can sleep until one of them is dispatched.
+        *control_flow = ControlFlow::Wait;
+
+        #[cfg(feature = "gamepad")]
+        {
+            *control_flow =
+                ControlFlow::WaitUntil(std::time::Instant::now() + gamepad::POLL_INTERVAL);
+            gamepads.poll(|source, key, state| {
+                if send_key(&inputs, &mut keypad, source, key, state) {
+                    window.request_redraw();
+                }
+            });
+        }
 
         match event {
             Event::RedrawRequested(_) => {
+                let width = overlay.frame_width() as usize;
                 let frame = pixels.get_frame();
 
-                cpu.draw(frame);
+                // Keep redrawing while pixels are fading out
+                if renderer.update(&vram) {
+                    window.request_redraw();
+                }
+
+                display::draw(renderer.brightness(), frame, width, &palette);
+                osd.draw(frame, width, &palette);
+                overlay.draw(frame, &keypad);
                 pixels.render().unwrap();
             }
+            Event::UserEvent(Output::Frame(ref frame)) => {
+                // Only the latest frame is kept, older ones are simply dropped
+                renderer.update(&vram);
+                vram.copy_from_slice(frame);
+                window.request_redraw();
+            }
+            Event::UserEvent(Output::Beep) => beep(440, 10),
+            Event::UserEvent(Output::KeypadUsage { keys_read, waiting }) => {
+                overlay.set_usage(keys_read, waiting);
+                window.request_redraw();
+            }
+            Event::UserEvent(Output::Stats {
+                frames_per_second,
+                instructions_per_frame,
+            }) => {
+                osd.set_emulation_stats(frames_per_second, instructions_per_frame);
+
+                if osd.stats_visible() {
+                    window.request_redraw();
+                }
+            }
+            Event::UserEvent(Output::Message {
+                ref text,
+                ref details,
+            }) => {
+                for line in details {
+                    println!("{}", line);
+                }
+
+                osd.show_message(text);
+                window.request_redraw();
+            }
             Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                window_id,
-            } if window_id == window.id() => *control_flow = ControlFlow::Exit,
-            _ => {
-                let (should_redraw, should_beep) = cpu.tick();
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                scancode,
+                                state,
+                                virtual_keycode,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let key = key_mapping.key(scancode, virtual_keycode);
 
-                if should_redraw {
+                if send_key(
+                    &inputs,
+                    &mut keypad,
+                    HostKey::Scancode(scancode),
+                    key,
+                    state,
+                ) {
                     window.request_redraw();
                 }
+            }
+            // The frame is scaled by the largest integer factor fitting in the window and centered, the remaining area
+            // being filled with the background color
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                pixels.resize_surface(size.width, size.height);
 
-                if should_beep {
-                    beep(440, 10);
+                if window.fullscreen().is_none() {
+                    geometry.width = size.width;
+                    geometry.height = size.height;
                 }
             }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::ScaleFactorChanged {
+                        ref new_inner_size, ..
+                    },
+                ..
+            } => pixels.resize_surface(new_inner_size.width, new_inner_size.height),
+            Event::WindowEvent {
+                event: WindowEvent::Moved(position),
+                ..
+            } if window.fullscreen().is_none() => {
+                geometry.x = position.x;
+                geometry.y = position.y;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                window_id,
+            } if window_id == window.id() => *control_flow = ControlFlow::Exit,
+            _ => {}
         }
 
         if input.update(&event) {
             if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
                 *control_flow = ControlFlow::Exit;
-                return;
             }
 
-            for (vkc, key) in key_mapping::KEY_MAPPING {
-                if input.key_pressed(vkc) {
-                    cpu.press_key(key);
-                    break;
+            // F1 shows or hides the on-screen keypad, which can be clicked with the mouse
+            if input.key_pressed(VirtualKeyCode::F1) {
+                overlay.toggle();
+                pixels.resize_buffer(overlay.frame_width(), SCREEN_HEIGHT.into());
+                window.request_redraw();
+            }
+
+            // F11 or Alt+Enter switches between fullscreen and windowed mode
+            if input.key_pressed(VirtualKeyCode::F11)
+                || (input.held_alt() && input.key_pressed(VirtualKeyCode::Return))
+            {
+                window.set_fullscreen(match window.fullscreen() {
+                    Some(_) => None,
+                    None => Some(Fullscreen::Borderless(None)),
+                });
+            }
+
+            // F2 cycles through the built-in themes, dropping the colors given on the command line or in the
+            // configuration file
+            if input.key_pressed(VirtualKeyCode::F2) {
+                theme = theme.next();
+                palette = theme.palette();
+                pixels.set_clear_color(clear_color(&palette));
+                osd.show_message(theme.to_string());
+                window.request_redraw();
+            }
+
+            // F3 shows or hides the performance stats
+            if input.key_pressed(VirtualKeyCode::F3) {
+                osd.toggle_stats();
+                window.request_redraw();
+            }
+
+            // F12 saves the screen, as displayed but without the on-screen keypad, to a PNG file
+            if input.key_pressed(VirtualKeyCode::F12) {
+                let path =
+                    screenshot::timestamped_path(&args.screenshot_dir, &args.rom_path, "png");
+
+                match screenshot::save(
+                    renderer.brightness(),
+                    &palette,
+                    args.screenshot_scale,
+                    &path,
+                ) {
+                    Ok(()) => {
+                        println!("Screenshot saved to {}", path.display());
+                        osd.show_message("Screenshot");
+                    }
+                    Err(error) => {
+                        eprintln!("Cannot save the screenshot: {}", error);
+                        osd.show_message("Screenshot error");
+                    }
                 }
 
-                if input.key_released(vkc) {
-                    cpu.release_key();
-                    break;
+                window.request_redraw();
+            }
+
+            // F9 starts recording the screen to a GIF, or stops the current recording
+            if input.key_pressed(VirtualKeyCode::F9) {
+                if recording {
+                    recording = false;
+                    osd.show_message("REC stopped");
+                    let _ = inputs.send(Input::StopRecording);
+                } else {
+                    let path =
+                        screenshot::timestamped_path(&args.screenshot_dir, &args.rom_path, "gif");
+
+                    match Recorder::create(&path, &palette, args.record_scale) {
+                        Ok(recorder) => {
+                            recording = true;
+                            println!("Recording to {}", path.display());
+                            osd.show_message("REC");
+                            let _ = inputs.send(Input::StartRecording(recorder));
+                        }
+                        Err(error) => {
+                            eprintln!("{}", error);
+                            osd.show_message("REC error");
+                        }
+                    }
+                }
+
+                window.request_redraw();
+            }
+
+            // F4 to F8 search the memory and freeze the bytes found, as the monitor does
+            for (number, key) in [
+                (4, VirtualKeyCode::F4),
+                (5, VirtualKeyCode::F5),
+                (6, VirtualKeyCode::F6),
+                (7, VirtualKeyCode::F7),
+                (8, VirtualKeyCode::F8),
+            ] {
+                match Cheat::from_key(number, input.held_shift()) {
+                    Some(cheat) if input.key_pressed(key) => {
+                        let _ = inputs.send(Input::Cheat(cheat));
+                    }
+                    _ => {}
+                }
+            }
+
+            if input.mouse_pressed(0) {
+                let key = input
+                    .mouse()
+                    .and_then(|position| pixels.window_pos_to_pixel(position).ok())
+                    .and_then(|(x, y)| overlay.key_at(x, y));
+
+                if send_key(
+                    &inputs,
+                    &mut keypad,
+                    HostKey::Mouse,
+                    key,
+                    ElementState::Pressed,
+                ) {
+                    window.request_redraw();
+                }
+            }
+
+            if input.mouse_released(0)
+                && send_key(
+                    &inputs,
+                    &mut keypad,
+                    HostKey::Mouse,
+                    None,
+                    ElementState::Released,
+                )
+            {
+                window.request_redraw();
+            }
+        }
+
+        // Wake up to erase the status message when it expires
+        if osd.expire_message() {
+            window.request_redraw();
+        }
+
+        if let Some(deadline) = osd.message_deadline() {
+            *control_flow = match *control_flow {
+                ControlFlow::Wait => ControlFlow::WaitUntil(deadline),
+                ControlFlow::WaitUntil(instant) => ControlFlow::WaitUntil(instant.min(deadline)),
+                control_flow => control_flow,
+            };
+        }
+
+        // Let the emulator finish its work (e.g. write the profile) before the process exits
+        if *control_flow == ControlFlow::Exit {
+            if let Some(emulator) = emulator.take() {
+                let _ = inputs.send(Input::Quit);
+                let _ = emulator.join();
+
+                geometry.fullscreen = window.fullscreen().is_some();
+
+                if let Err(error) = geometry.save() {
+                    eprintln!("Cannot save the geometry of the window: {}", error);
                 }
             }
         }
     });
 }
 
+// Replay a movie as fast as possible and record it, to share a session (e.g. in a bug report) as a video.
+fn replay(args: ReplayArgs, config: Config) -> Result<(), Box<dyn Error>> {
+    let rom = load_rom(&args.rom_path)?;
+    let movie = Movie::load(&args.movie_path)?;
+    let cpu = Cpu::with_seed(rom.clone(), movie.super_chip, movie.seed)?;
+    let (_, palette) = palette::resolve(&args.palette, &config.display, &[]);
+    let options = emulator::Options {
+        ipf: movie.ipf,
+        idle_skip: movie.idle_skip,
+        profiler: None,
+        replay: Some(movie),
+        movie: None,
+        recorder: Some(Recorder::create(&args.output, &palette, args.scale)?),
+        cheats: Cheats::default(),
+        rom,
+    };
+
+    emulator::replay(cpu, options);
+
+    Ok(())
+}
+
+// Forward a key event to the emulator, whatever its source (keyboard, mouse or gamepad), and let the caller know if
+// the state of the keypad has changed.
+fn send_key(
+    inputs: &Sender<Input>,
+    keypad: &mut Keypad,
+    source: HostKey,
+    key: Option<u8>,
+    state: ElementState,
+) -> bool {
+    let input = match (state, key) {
+        (ElementState::Pressed, Some(key)) if keypad.press(source, key) => Input::PressKey(key),
+        (ElementState::Released, _) => match keypad.release(source) {
+            Some(key) => Input::ReleaseKey(key),
+            None => return false,
+        },
+        _ => return false,
+    };
+
+    inputs.send(input).is_ok()
+}
+
+// Options given on the command line take precedence over the configuration file, and a user-defined mapping takes
+// precedence over the built-in layouts. Terminals don't report scancodes, so the keys cannot be mapped by their physical
+// position in the terminal.
+fn key_mapping(args: &RunArgs, config: &KeyboardConfig) -> KeyMapping {
+    if !args.tui
+        && !args.debug
+        && (args.physical_keys || (args.layout.is_none() && config.physical))
+    {
+        return KeyMapping::physical();
+    }
+
+    match args.layout.or(config.layout) {
+        None if !config.keys.is_empty() => KeyMapping::from_bindings(
+            config
+                .keys
+                .iter()
+                .map(|(code, key)| (HostKey::Virtual(*code), *key)),
+        ),
+        layout => KeyMapping::from_layout(layout.unwrap_or(Layout::Qwerty)),
+    }
+}
+
+// The area around the screen, visible when the window doesn't have the same aspect ratio, takes the background color.
+fn clear_color(palette: &Palette) -> pixels::wgpu::Color {
+    let [red, green, blue] = palette.background().0.map(|value| value as f64 / 255.0);
+
+    pixels::wgpu::Color {
+        r: red,
+        g: green,
+        b: blue,
+        a: 1.0,
+    }
+}
+
 #[cfg(windows)]
 fn beep(frequency: u32, duration: u32) {
     use winapi::um::utilapiset;
@@ -109,7 +760,7 @@ fn beep(frequency: u32, duration: u32) {
     }
 }
 #[cfg(not(windows))]
-fn beep(frequency: u32, duration: u32) {
+fn beep(_frequency: u32, _duration: u32) {
     // TODO: implement beep for other platforms
     println!("beep() is not supported")
 }
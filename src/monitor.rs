@@ -0,0 +1,521 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    assembler,
+    cheats::{Cheats, Comparison, Search, MAX_CANDIDATES_SHOWN},
+    cli::MonitorArgs,
+    cpu::{Cpu, ROM_ADDRESS},
+    database, platform,
+    settings::RomSettings,
+    symbols::Symbols,
+    DEFAULT_IPF,
+};
+
+// Bytes shown per line of a memory dump, and what is shown when no count is given.
+const BYTES_PER_LINE: usize = 16;
+const DEFAULT_DUMP_SIZE: usize = 0x40;
+const DEFAULT_INSTRUCTIONS: usize = 16;
+
+const HELP: &str = "\
+All numbers are in hexadecimal, and addresses can also be labels from the symbol file of the ROM.
+In instructions, a number that reads as an operand (`B` or `F`) must be written with a leading zero, e.g. `0B`.
+
+m [addr] [count]          Show the memory, from where the last dump ended by default
+w addr byte...            Write bytes to the memory
+a addr instruction        Assemble an instruction as in a source file, e.g. `a 200 LD V0, 0x05`
+d [addr] [count]          Disassemble instructions, from where the last disassembly ended by default
+r [register value]        Show the registers, or set one of them (V0-VF, I, PC, DT or ST)
+s [count]                 Execute instructions, one by default
+k [key...]                Hold the given keys of the keypad while executing instructions, none by default
+f addr count byte         Fill the memory with a byte
+c addr count destination  Copy the memory, the ranges can overlap
+l path [addr]             Load a binary file to the memory, at 200 by default
+v path addr count         Save the memory to a binary file
+search                    Start searching for the address of a value, all the memory being a candidate
+search = byte             Keep the candidates equal to a byte
+search how                Keep the candidates that changed, unchanged, increased or decreased since the last search
+freeze addr [byte]        Write a byte (the current one by default) to an address before every frame
+unfreeze addr             Stop writing to an address
+cheats [save]             Show the frozen addresses, or save them as the cheats of the ROM
+h                         Show this help
+q                         Quit";
+
+// A machine-language monitor, as found on the home computers of the 80s: commands are read from stdin to examine and
+// modify the state of the paused CPU, and to execute the program instruction by instruction.
+pub fn run(args: MonitorArgs) -> Result<(), Box<dyn Error>> {
+    let rom = crate::load_rom(&args.rom_path)?;
+    let settings = RomSettings::load(&args.rom_path)?.or(database::settings(&rom)?);
+    let super_chip = args.super_chip
+        || settings
+            .super_chip
+            .unwrap_or_else(|| platform::detect(&rom).super_chip());
+    let cpu = Cpu::new(rom.clone(), super_chip)?;
+    let mut monitor = Monitor {
+        cheats: Cheats::load(&rom)?,
+        search: None,
+        rom,
+        dump_address: cpu.pc(),
+        disassembly_address: cpu.pc(),
+        cpu,
+        ipf: args.ipf.or(settings.ipf).unwrap_or(DEFAULT_IPF),
+        instructions: 0,
+        symbols: Symbols::load(&args.rom_path)?,
+    };
+
+    println!("Type `h` for help.");
+    println!("{}", monitor.registers());
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                println!();
+                return Ok(());
+            }
+        };
+
+        match monitor.command(&line) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(message) => println!("Error: {}", message),
+        }
+    }
+}
+
+struct Monitor {
+    cpu: Cpu,
+    ipf: u32,
+
+    // Instructions executed since the timers were last decremented.
+    instructions: u32,
+
+    symbols: Symbols,
+
+    // The ROM as loaded, identifying the cheats of the ROM.
+    rom: Vec<u8>,
+    cheats: Cheats,
+    search: Option<Search>,
+
+    // Where `m` and `d` go on without an address.
+    dump_address: u16,
+    disassembly_address: u16,
+}
+
+impl Monitor {
+    // Run a command, returning `false` to quit.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments: Vec<&str> = rest.split_whitespace().collect();
+
+        match (command.to_lowercase().as_str(), arguments.as_slice()) {
+            ("", _) => {}
+            ("m", [..]) if arguments.len() <= 2 => {
+                let start = match arguments.first() {
+                    Some(address) => self.address(address)?,
+                    None => self.dump_address,
+                };
+                let count = match arguments.get(1) {
+                    Some(count) => self.count(count)?,
+                    None => DEFAULT_DUMP_SIZE,
+                };
+
+                self.dump_address = self.range(start, count)?.end as u16 % self.ram_size();
+                print!("{}", self.dump(start, count));
+            }
+            ("w", [address, bytes @ ..]) if !bytes.is_empty() => {
+                let address = self.address(address)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| self.byte(byte))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.write(address, &bytes)?;
+            }
+            ("a", [address, _, ..]) => {
+                let address = self.address(address)?;
+                let (_, text) = rest
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or_default();
+                let instruction =
+                    assembler::assemble_instruction(text, &self.symbols.labels, parse_number)?;
+
+                self.write(address, &instruction.to_be_bytes())?;
+                println!("{}", self.disassembly_line(address));
+                self.disassembly_address = address + 2;
+            }
+            ("d", [..]) if arguments.len() <= 2 => {
+                let start = match arguments.first() {
+                    Some(address) => self.address(address)?,
+                    None => self.disassembly_address,
+                };
+                let count = match arguments.get(1) {
+                    Some(count) => self.count(count)?,
+                    None => DEFAULT_INSTRUCTIONS,
+                };
+                let range = self.range(start, count * 2)?;
+
+                for address in range.clone().step_by(2) {
+                    println!("{}", self.disassembly_line(address as u16));
+                }
+
+                self.disassembly_address = range.end as u16 % self.ram_size();
+            }
+            ("r", []) => println!("{}", self.registers()),
+            ("r", [register, value]) => {
+                self.set_register(register, value)?;
+                println!("{}", self.registers());
+            }
+            ("s", [..]) if arguments.len() <= 1 => {
+                let count = match arguments.first() {
+                    Some(count) => self.count(count)?,
+                    None => 1,
+                };
+
+                let result = self.step(count);
+
+                self.disassembly_address = self.cpu.pc();
+                println!("{}", self.registers());
+                result?;
+            }
+            ("k", keys) => {
+                let keys = keys
+                    .iter()
+                    .map(|key| match self.byte(key)? {
+                        key @ 0..=0xF => Ok(key),
+                        _ => Err(format!("Invalid key `{}`", key)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                for key in 0..=0xF {
+                    match keys.contains(&key) {
+                        true => self.cpu.press_key(key),
+                        false => self.cpu.release_key(key),
+                    }
+                }
+            }
+            ("search", []) => {
+                self.search = Some(Search::new(self.cpu.ram()));
+                println!("{} candidates", self.cpu.ram().len());
+            }
+            ("search", [how] | ["=", how]) => {
+                let comparison = match (arguments.len(), how.to_lowercase().as_str()) {
+                    (2, _) => Comparison::Equal(self.byte(how)?),
+                    (_, "changed") => Comparison::Changed,
+                    (_, "unchanged") => Comparison::Unchanged,
+                    (_, "increased") => Comparison::Increased,
+                    (_, "decreased") => Comparison::Decreased,
+                    _ => return Err(format!("Invalid search `{}`, type `h` for help", line)),
+                };
+                let search = self
+                    .search
+                    .as_mut()
+                    .ok_or("Start a search with `search` first")?;
+
+                search.filter(self.cpu.ram(), comparison);
+                self.print_candidates();
+            }
+            ("freeze", [address] | [address, _]) => {
+                let address = self.address(address)?;
+                let value = match arguments.get(1) {
+                    Some(value) => self.byte(value)?,
+                    None => self.cpu.ram()[address as usize],
+                };
+
+                self.cheats.freezes.insert(address, value);
+                self.cpu.write(address, value);
+            }
+            ("unfreeze", [address]) => {
+                let address = self.address(address)?;
+
+                if self.cheats.freezes.remove(&address).is_none() {
+                    return Err(format!("{:03X} isn't frozen", address));
+                }
+            }
+            ("cheats", []) => {
+                for (address, value) in &self.cheats.freezes {
+                    println!("{}={:02X}", self.symbols.annotate(*address), value);
+                }
+            }
+            ("cheats", ["save"]) => {
+                let path = self
+                    .cheats
+                    .save(&self.rom)
+                    .map_err(|error| error.to_string())?;
+
+                println!("Cheats saved to {}", path.display());
+            }
+            ("f", [address, count, byte]) => {
+                let range = self.range(self.address(address)?, self.count(count)?)?;
+                let byte = self.byte(byte)?;
+
+                for address in range {
+                    self.cpu.write(address as u16, byte);
+                }
+            }
+            ("c", [address, count, destination]) => {
+                let count = self.count(count)?;
+                let source = self.range(self.address(address)?, count)?;
+                let bytes = self.cpu.ram()[source].to_vec();
+
+                self.write(self.address(destination)?, &bytes)?;
+            }
+            ("l", [path] | [path, _]) => {
+                let address = match arguments.get(1) {
+                    Some(address) => self.address(address)?,
+                    None => ROM_ADDRESS,
+                };
+                let bytes =
+                    fs::read(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+
+                self.write(address, &bytes)?;
+                println!("Loaded {} bytes to {:03X}", bytes.len(), address);
+            }
+            ("v", [path, address, count]) => {
+                let range = self.range(self.address(address)?, self.count(count)?)?;
+                let bytes = &self.cpu.ram()[range];
+
+                fs::write(Path::new(path), bytes)
+                    .map_err(|error| format!("Cannot write {}: {}", path, error))?;
+                println!("Saved {} bytes to {}", bytes.len(), path);
+            }
+            ("h" | "?", []) => println!("{}", HELP),
+            ("q", []) => return Ok(false),
+            _ => return Err(format!("Invalid command `{}`, type `h` for help", line)),
+        }
+
+        Ok(true)
+    }
+
+    // Execute instructions, decrementing the timers every IPF instructions and writing the frozen bytes at the start of
+    // every frame. The execution stops before an instruction that would make the CPU panic, e.g. an unsupported one.
+    fn step(&mut self, count: usize) -> Result<(), String> {
+        for _ in 0..count {
+            if self.instructions == 0 {
+                self.cheats.apply(&mut self.cpu);
+            }
+
+            if let Some(fault) = self.cpu.fault() {
+                return Err(fault);
+            }
+
+            self.cpu.step();
+            self.instructions += 1;
+
+            if self.instructions >= self.ipf {
+                self.cpu.decrement_timers();
+                self.instructions = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Show the remaining candidates of the search with their value, unless there are too many of them.
+    fn print_candidates(&self) {
+        let candidates = self.search.as_ref().map_or(&[][..], Search::candidates);
+
+        if candidates.len() <= MAX_CANDIDATES_SHOWN {
+            for address in candidates {
+                println!(
+                    "{}={:02X}",
+                    self.symbols.annotate(*address),
+                    self.cpu.ram()[*address as usize]
+                );
+            }
+        }
+
+        println!("{} candidates", candidates.len());
+    }
+
+    fn write(&mut self, address: u16, bytes: &[u8]) -> Result<(), String> {
+        let range = self.range(address, bytes.len())?;
+
+        for (address, byte) in range.zip(bytes) {
+            self.cpu.write(address as u16, *byte);
+        }
+
+        Ok(())
+    }
+
+    fn set_register(&mut self, register: &str, value: &str) -> Result<(), String> {
+        match register.to_uppercase().as_str() {
+            "PC" => {
+                let pc = self.address(value)?;
+
+                // The two bytes of the instruction must be in the memory
+                self.range(pc, 2)?;
+                self.cpu.set_pc(pc);
+            }
+            "I" => {
+                let i = self.address(value)?;
+
+                self.cpu.set_i(i);
+            }
+            "DT" => {
+                let value = self.byte(value)?;
+
+                self.cpu.set_delay_timer(value);
+            }
+            "ST" => {
+                let value = self.byte(value)?;
+
+                self.cpu.set_sound_timer(value);
+            }
+            name => {
+                let x = name
+                    .strip_prefix('V')
+                    .filter(|x| x.len() == 1)
+                    .and_then(|x| usize::from_str_radix(x, 16).ok())
+                    .ok_or(format!("Unknown register `{}`", register))?;
+                let value = self.byte(value)?;
+
+                self.cpu.set_v(x, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn registers(&self) -> String {
+        let cpu = &self.cpu;
+        let v: Vec<String> = cpu
+            .v()
+            .iter()
+            .enumerate()
+            .map(|(x, value)| format!("V{:X}={:02X}", x, value))
+            .collect();
+
+        format!(
+            "{}\nPC={:03X} I={:03X} DT={:02X} ST={:02X} SP={:X}\n{}",
+            v.join(" "),
+            cpu.pc(),
+            cpu.i(),
+            cpu.delay_timer(),
+            cpu.sound_timer(),
+            cpu.stack().len(),
+            self.disassembly_line(cpu.pc())
+        )
+    }
+
+    // A line of disassembly, preceded by the label of the address if it has one.
+    fn disassembly_line(&self, address: u16) -> String {
+        let cpu = &self.cpu;
+
+        // PC can go past the end of the memory, e.g. after executing the last instruction
+        if address as usize >= cpu.ram().len() - 1 {
+            return format!("{:03X}  past the end of the memory", address);
+        }
+
+        let label = match self.symbols.label_at(address) {
+            Some(label) => format!("{}:\n", label),
+            None => String::new(),
+        };
+        let operation = match cpu.try_peek_at(address) {
+            Some(operation) => self.symbols.disassemble(operation),
+            None => "???".to_string(),
+        };
+
+        format!(
+            "{}{:03X}  {:04X}  {}",
+            label,
+            address,
+            cpu.instruction_at(address),
+            operation
+        )
+    }
+
+    fn dump(&self, start: u16, count: usize) -> String {
+        let ram = self.cpu.ram();
+        let mut dump = String::new();
+
+        for line_start in (start as usize..start as usize + count).step_by(BYTES_PER_LINE) {
+            let bytes = &ram[line_start..(line_start + BYTES_PER_LINE).min(start as usize + count)];
+            let hexadecimal: Vec<String> =
+                bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7E => *byte as char,
+                    _ => '.',
+                })
+                .collect();
+
+            dump += &format!(
+                "{:03X}  {:<width$}  {}\n",
+                line_start,
+                hexadecimal.join(" "),
+                text,
+                width = BYTES_PER_LINE * 3 - 1
+            );
+        }
+
+        dump
+    }
+
+    fn address(&self, text: &str) -> Result<u16, String> {
+        let address = match self.symbols.labels.get(text) {
+            Some(address) => *address,
+            None => {
+                parse_number(text).ok_or(format!("Unknown label or invalid address `{}`", text))?
+            }
+        };
+
+        match (address as usize) < self.cpu.ram().len() {
+            true => Ok(address),
+            false => Err(format!("{:X} is outside of the memory", address)),
+        }
+    }
+
+    fn count(&self, text: &str) -> Result<usize, String> {
+        parse_number(text)
+            .map(|count| count as usize)
+            .ok_or(format!("Invalid count `{}`", text))
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, String> {
+        parse_number(text)
+            .and_then(|byte| u8::try_from(byte).ok())
+            .ok_or(format!("Invalid byte `{}`", text))
+    }
+
+    // The addresses of `count` bytes from `start`, which must all be in the memory.
+    fn range(&self, start: u16, count: usize) -> Result<std::ops::Range<usize>, String> {
+        let end = start as usize + count;
+
+        match end <= self.cpu.ram().len() {
+            true => Ok(start as usize..end),
+            false => Err(format!(
+                "{:X} bytes from {:03X} go past the end of the memory",
+                count, start
+            )),
+        }
+    }
+
+    fn ram_size(&self) -> u16 {
+        self.cpu.ram().len() as u16
+    }
+}
+
+// Numbers are in hexadecimal, optionally prefixed by `$` or `0x`.
+fn parse_number(text: &str) -> Option<u16> {
+    let text = text.trim_start_matches('$');
+    let text = text
+        .strip_prefix("0x")
+        .or(text.strip_prefix("0X"))
+        .unwrap_or(text);
+
+    u16::from_str_radix(text, 16).ok()
+}
@@ -0,0 +1,90 @@
+use std::{error::Error, fs, path::Path};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+// A movie records the key presses and releases of a session, frame by frame. The emulation is deterministic once the
+// seed of the random number generator is known, so replaying these inputs reproduces the session exactly.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Movie {
+    // TOML integers are signed, so the seed is written as an hexadecimal string.
+    #[serde(
+        serialize_with = "serialize_seed",
+        deserialize_with = "deserialize_seed"
+    )]
+    pub seed: u64,
+    pub super_chip: bool,
+    pub ipf: u32,
+
+    // Idle loops change where frames end, so the movie must be replayed with the same setting. Movies recorded before
+    // it was saved were recorded with the default one.
+    #[serde(default = "default_idle_skip")]
+    pub idle_skip: bool,
+
+    // Number of frames of the session.
+    pub frames: u64,
+
+    #[serde(default)]
+    pub events: Vec<KeyEvent>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct KeyEvent {
+    // Index of the frame before which the key is pressed or released.
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+impl Movie {
+    pub fn new(seed: u64, super_chip: bool, ipf: u32, idle_skip: bool) -> Movie {
+        Movie {
+            seed,
+            super_chip,
+            ipf,
+            idle_skip,
+            frames: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+
+        toml::from_str(&content)
+            .map_err(|error| format!("Invalid movie in {}: {}", path.display(), error).into())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let content = toml::to_string(self)?;
+
+        fs::write(path, content)
+            .map_err(|error| format!("Cannot write {}: {}", path.display(), error).into())
+    }
+
+    // The events to apply before executing the given frame. They're sorted by frame as they're recorded in order.
+    pub fn events_at(&self, frame: u64) -> impl Iterator<Item = &KeyEvent> {
+        let start = self.events.partition_point(|event| event.frame < frame);
+
+        self.events[start..]
+            .iter()
+            .take_while(move |event| event.frame == frame)
+    }
+}
+
+fn default_idle_skip() -> bool {
+    true
+}
+
+fn serialize_seed<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{:016X}", seed))
+}
+
+fn deserialize_seed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let seed = String::deserialize(deserializer)?;
+
+    u64::from_str_radix(seed.trim_start_matches("0x"), 16)
+        .map_err(|_| D::Error::custom(format!("invalid seed `{}`", seed)))
+}
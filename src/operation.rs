@@ -1,5 +1,8 @@
+use std::fmt;
+
 use crate::nibble::Nibble;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Operation {
     // 0NNN
     CallMachineCodeRoutineAt { address: u16 },
@@ -112,6 +115,12 @@ pub enum Operation {
 
 impl Operation {
     pub fn decode(instruction: u16, super_chip: bool) -> Operation {
+        Operation::try_decode(instruction, super_chip)
+            .unwrap_or_else(|| panic!("Unsupported instruction {:04x}", instruction))
+    }
+
+    // Decode an instruction that may not be one, e.g. sprite data shown by the debugger, without panicking.
+    pub fn try_decode(instruction: u16, super_chip: bool) -> Option<Operation> {
         // CHIP-8 instructions are divided into broad categories by the first "nibble", or "half-byte", which is the
         // first hexadecimal number. Although every instruction will have a first nibble that tells you what kind of
         // instruction it is, the rest of the nibbles will have different meanings.
@@ -125,7 +134,7 @@ impl Operation {
         // - NNN: The second, third and fourth nibbles. A 12-bit immediate memory address.
         let nibbles = &instruction.get_nibbles();
 
-        match nibbles {
+        let operation = match nibbles {
             (0x00, 0x00, 0x0E, 0x00) => Operation::ClearScreen,
             (0x01, _, _, _) => Operation::JumpTo {
                 address: instruction & 0x0FFF,
@@ -238,7 +247,117 @@ impl Operation {
                 address: instruction & 0x0FFF,
             },
 
-            _ => panic!("Unsupported instruction {:04x}", instruction),
+            _ => return None,
+        };
+
+        Some(operation)
+    }
+
+    // The name of the variant, used to aggregate operations regardless of their operands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::CallMachineCodeRoutineAt { .. } => "CallMachineCodeRoutineAt",
+            Operation::ClearScreen => "ClearScreen",
+            Operation::ReturnFromSubroutine => "ReturnFromSubroutine",
+            Operation::JumpTo { .. } => "JumpTo",
+            Operation::CallSubroutineAt { .. } => "CallSubroutineAt",
+            Operation::SkipNextInstructionIfVXEquals { .. } => "SkipNextInstructionIfVXEquals",
+            Operation::SkipNextInstructionIfVXNotEquals { .. } => {
+                "SkipNextInstructionIfVXNotEquals"
+            }
+            Operation::SkipNextInstructionIfVXEqualsVY { .. } => "SkipNextInstructionIfVXEqualsVY",
+            Operation::SetVXTo { .. } => "SetVXTo",
+            Operation::AddToVX { .. } => "AddToVX",
+            Operation::SetVXToVY { .. } => "SetVXToVY",
+            Operation::SetVXToVXOrVY { .. } => "SetVXToVXOrVY",
+            Operation::SetVXToVXAndVY { .. } => "SetVXToVXAndVY",
+            Operation::SetVXToVXXorVY { .. } => "SetVXToVXXorVY",
+            Operation::AddVYToVX { .. } => "AddVYToVX",
+            Operation::SubtractVYFromVX { .. } => "SubtractVYFromVX",
+            Operation::RightShiftVX { .. } => "RightShiftVX",
+            Operation::SubtractVXFromVY { .. } => "SubtractVXFromVY",
+            Operation::LeftShiftVX { .. } => "LeftShiftVX",
+            Operation::SkipNextInstructionIfVXNotEqualsVY { .. } => {
+                "SkipNextInstructionIfVXNotEqualsVY"
+            }
+            Operation::SetITo { .. } => "SetITo",
+            Operation::JumpToPlusV0 { .. } => "JumpToPlusV0",
+            Operation::JumpToPlusVX { .. } => "JumpToPlusVX",
+            Operation::SetVXToVXAndRandomNumber { .. } => "SetVXToVXAndRandomNumber",
+            Operation::DrawSpriteAt { .. } => "DrawSpriteAt",
+            Operation::SkipNextInstructionIfKeyInVXPressed { .. } => {
+                "SkipNextInstructionIfKeyInVXPressed"
+            }
+            Operation::SkipNextInstructionIfKeyInVXNotPressed { .. } => {
+                "SkipNextInstructionIfKeyInVXNotPressed"
+            }
+            Operation::SetVXToDelayTimer { .. } => "SetVXToDelayTimer",
+            Operation::AwaitKeyPress { .. } => "AwaitKeyPress",
+            Operation::SetDelayTimerToVX { .. } => "SetDelayTimerToVX",
+            Operation::SetSoundTimerToVX { .. } => "SetSoundTimerToVX",
+            Operation::AddVXToI { .. } => "AddVXToI",
+            Operation::SetIToSpriteLocationForCharacterInVX { .. } => {
+                "SetIToSpriteLocationForCharacterInVX"
+            }
+            Operation::StoreBinaryCodedDecimalOfVX { .. } => "StoreBinaryCodedDecimalOfVX",
+            Operation::StoreFromV0ToVX { .. } => "StoreFromV0ToVX",
+            Operation::FillFromV0ToVX { .. } => "FillFromV0ToVX",
+        }
+    }
+}
+
+// Operations are displayed using the mnemonics of Cowgod's Chip-8 technical reference.
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operation::CallMachineCodeRoutineAt { address } => write!(f, "SYS 0x{:03X}", address),
+            Operation::ClearScreen => write!(f, "CLS"),
+            Operation::ReturnFromSubroutine => write!(f, "RET"),
+            Operation::JumpTo { address } => write!(f, "JP 0x{:03X}", address),
+            Operation::CallSubroutineAt { address } => write!(f, "CALL 0x{:03X}", address),
+            Operation::SkipNextInstructionIfVXEquals { x, value } => {
+                write!(f, "SE V{:X}, 0x{:02X}", x, value)
+            }
+            Operation::SkipNextInstructionIfVXNotEquals { x, value } => {
+                write!(f, "SNE V{:X}, 0x{:02X}", x, value)
+            }
+            Operation::SkipNextInstructionIfVXEqualsVY { x, y } => {
+                write!(f, "SE V{:X}, V{:X}", x, y)
+            }
+            Operation::SetVXTo { x, value } => write!(f, "LD V{:X}, 0x{:02X}", x, value),
+            Operation::AddToVX { x, value } => write!(f, "ADD V{:X}, 0x{:02X}", x, value),
+            Operation::SetVXToVY { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Operation::SetVXToVXOrVY { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Operation::SetVXToVXAndVY { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Operation::SetVXToVXXorVY { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Operation::AddVYToVX { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Operation::SubtractVYFromVX { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Operation::RightShiftVX { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Operation::SubtractVXFromVY { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Operation::LeftShiftVX { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Operation::SkipNextInstructionIfVXNotEqualsVY { x, y } => {
+                write!(f, "SNE V{:X}, V{:X}", x, y)
+            }
+            Operation::SetITo { address } => write!(f, "LD I, 0x{:03X}", address),
+            Operation::JumpToPlusV0 { address } => write!(f, "JP V0, 0x{:03X}", address),
+            Operation::JumpToPlusVX { x, address } => write!(f, "JP V{:X}, 0x{:03X}", x, address),
+            Operation::SetVXToVXAndRandomNumber { x, value } => {
+                write!(f, "RND V{:X}, 0x{:02X}", x, value)
+            }
+            Operation::DrawSpriteAt { x, y, height } => {
+                write!(f, "DRW V{:X}, V{:X}, {}", x, y, height)
+            }
+            Operation::SkipNextInstructionIfKeyInVXPressed { x } => write!(f, "SKP V{:X}", x),
+            Operation::SkipNextInstructionIfKeyInVXNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Operation::SetVXToDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            Operation::AwaitKeyPress { x } => write!(f, "LD V{:X}, K", x),
+            Operation::SetDelayTimerToVX { x } => write!(f, "LD DT, V{:X}", x),
+            Operation::SetSoundTimerToVX { x } => write!(f, "LD ST, V{:X}", x),
+            Operation::AddVXToI { x } => write!(f, "ADD I, V{:X}", x),
+            Operation::SetIToSpriteLocationForCharacterInVX { x } => write!(f, "LD F, V{:X}", x),
+            Operation::StoreBinaryCodedDecimalOfVX { x } => write!(f, "LD B, V{:X}", x),
+            Operation::StoreFromV0ToVX { x } => write!(f, "LD [I], V{:X}", x),
+            Operation::FillFromV0ToVX { x } => write!(f, "LD V{:X}, [I]", x),
         }
     }
 }
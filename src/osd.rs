@@ -0,0 +1,209 @@
+use std::time::{Duration, Instant};
+
+use crate::{palette::Palette, SCREEN_HEIGHT};
+
+// How long a status message stays on screen.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+// Every character is 3x5 pixels, followed by a blank column, so that a line of 16 characters fits in the width of the
+// screen. Text is drawn on a box of the background color, one pixel larger than the text, to be readable over the game.
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+// The glyphs of the characters supported by the on-screen display, independent of the font of the CHIP-8. Every row
+// of a glyph is stored in the 3 lowest bits of a byte, the most significant one being the leftmost pixel.
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 43] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b011, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('?', [0b111, 0b001, 0b011, 0b000, 0b010]),
+];
+
+// Performance figures measured over the last second.
+#[derive(Default)]
+struct Stats {
+    // Frames drawn in the window.
+    frames_per_second: f32,
+
+    // Instructions actually executed per frame, which is lower than the IPF while the program is idle.
+    instructions_per_frame: f32,
+
+    // Speed of the emulation compared to the 60 frames per second of the original hardware.
+    speed: f32,
+}
+
+// The on-screen display shows a status message for a short time at the bottom of the screen (e.g. when the palette is
+// changed), and optionally a line of performance stats at the top.
+#[derive(Default)]
+pub struct Osd {
+    message: Option<(String, Instant)>,
+    stats_visible: bool,
+    stats: Stats,
+
+    // Frames drawn since `redraws_since`.
+    redraws: u32,
+    redraws_since: Option<Instant>,
+}
+
+impl Osd {
+    pub fn show_message(&mut self, message: impl Into<String>) {
+        self.message = Some((
+            message.into().to_uppercase(),
+            Instant::now() + MESSAGE_DURATION,
+        ));
+    }
+
+    // The time at which the current message disappears, when the screen has to be redrawn.
+    pub fn message_deadline(&self) -> Option<Instant> {
+        self.message.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    // Remove the current message if it has expired, returning `true` if it has.
+    pub fn expire_message(&mut self) -> bool {
+        match &self.message {
+            Some((_, deadline)) if Instant::now() >= *deadline => {
+                self.message = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.stats_visible = !self.stats_visible;
+    }
+
+    pub fn stats_visible(&self) -> bool {
+        self.stats_visible
+    }
+
+    // Update the stats of the emulation, measured by the emulator thread over the last second.
+    pub fn set_emulation_stats(&mut self, frames_per_second: f32, instructions_per_frame: f32) {
+        self.stats.instructions_per_frame = instructions_per_frame;
+        self.stats.speed = frames_per_second / 60.0;
+
+        // The window is only redrawn when something changes, so no redraw for a second means 0 frames per second
+        let now = Instant::now();
+        let since = *self.redraws_since.get_or_insert(now);
+        let elapsed = (now - since).as_secs_f32();
+
+        if elapsed > 0.0 {
+            self.stats.frames_per_second = self.redraws as f32 / elapsed;
+        }
+
+        self.redraws = 0;
+        self.redraws_since = Some(now);
+    }
+
+    // Draw the message and the stats over the frame buffer, whose width is given in pixels.
+    pub fn draw(&mut self, frame: &mut [u8], frame_width: usize, palette: &Palette) {
+        self.redraws += 1;
+
+        if self.stats_visible {
+            let stats = format!(
+                "{:.0}FPS {:.0}IPF {:.0}%",
+                self.stats.frames_per_second,
+                self.stats.instructions_per_frame,
+                self.stats.speed * 100.0
+            );
+
+            draw_text(frame, frame_width, 1, &stats, palette);
+        }
+
+        if let Some((message, _)) = &self.message {
+            let y = SCREEN_HEIGHT as usize - GLYPH_HEIGHT - 1;
+
+            draw_text(frame, frame_width, y, message, palette);
+        }
+    }
+}
+
+// Draw a line of text starting at the given row, on the left of the screen. Characters without a glyph are drawn as
+// `?`, and the text is clipped to the width of the frame.
+fn draw_text(frame: &mut [u8], frame_width: usize, y: usize, text: &str, palette: &Palette) {
+    let background = palette.color(0).0;
+    let foreground = palette.color(1).0;
+    let columns = (text.chars().count() * ADVANCE + 1).min(frame_width);
+
+    let mut set_pixel = |x: usize, y: usize, color: [u8; 3]| {
+        if x < frame_width {
+            let offset = (y * frame_width + x) * 4;
+
+            if let Some(pixel) = frame.get_mut(offset..offset + 4) {
+                pixel.copy_from_slice(&[color[0], color[1], color[2], 0xFF]);
+            }
+        }
+    };
+
+    // The box around the text
+    for row in y - 1..y + GLYPH_HEIGHT + 1 {
+        for x in 0..columns {
+            set_pixel(x, row, background);
+        }
+    }
+
+    for (index, c) in text.chars().enumerate() {
+        let glyph = glyph(c);
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) != 0 {
+                    set_pixel(1 + index * ADVANCE + column, y + row, foreground);
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    if c == ' ' {
+        return [0; GLYPH_HEIGHT];
+    }
+
+    GLYPHS
+        .iter()
+        .find(|(character, _)| *character == c)
+        .or_else(|| GLYPHS.iter().find(|(character, _)| *character == '?'))
+        .map(|(_, glyph)| *glyph)
+        .unwrap_or_default()
+}
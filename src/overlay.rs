@@ -0,0 +1,101 @@
+use crate::{
+    font::FONT,
+    key_mapping::{Keypad, KEYPAD},
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+// The on-screen keypad is drawn on the right of the game, as a 4x4 grid of 8x8 pixels cells.
+const CELL_SIZE: usize = 8;
+const WIDTH: usize = CELL_SIZE * 4;
+
+const BACKGROUND: [u8; 4] = [0x20, 0x20, 0x20, 0xFF];
+const GRID: [u8; 4] = [0x50, 0x50, 0x50, 0xFF];
+const GRID_WAITING: [u8; 4] = [0xD0, 0xA0, 0x20, 0xFF];
+const GLYPH: [u8; 4] = [0xC0, 0xC0, 0xC0, 0xFF];
+const HELD: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const READ_MARKER: [u8; 4] = [0x40, 0xC0, 0x40, 0xFF];
+
+#[derive(Default)]
+pub struct KeypadOverlay {
+    visible: bool,
+
+    // The keys checked by the program (one bit per key), which are underlined to help players discover them.
+    keys_read: u16,
+
+    // The grid is highlighted while the program waits for any key with FX0A.
+    waiting: bool,
+}
+
+impl KeypadOverlay {
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn set_usage(&mut self, keys_read: u16, waiting: bool) {
+        self.keys_read = keys_read;
+        self.waiting = waiting;
+    }
+
+    // Width of the frame buffer needed to draw the game and the overlay.
+    pub fn frame_width(&self) -> u32 {
+        match self.visible {
+            true => (SCREEN_WIDTH as usize + WIDTH) as u32,
+            false => SCREEN_WIDTH as u32,
+        }
+    }
+
+    // The keypad value of the cell found at the given frame buffer coordinates, if any.
+    pub fn key_at(&self, x: usize, y: usize) -> Option<u8> {
+        let x = x.checked_sub(SCREEN_WIDTH as usize)?;
+
+        match self.visible && x < WIDTH && y < SCREEN_HEIGHT as usize {
+            true => Some(KEYPAD[(y / CELL_SIZE) * 4 + x / CELL_SIZE]),
+            false => None,
+        }
+    }
+
+    pub fn draw(&self, frame: &mut [u8], keypad: &Keypad) {
+        if !self.visible {
+            return;
+        }
+
+        let frame_width = self.frame_width() as usize;
+
+        for (index, key) in KEYPAD.iter().enumerate() {
+            let left = SCREEN_WIDTH as usize + (index % 4) * CELL_SIZE;
+            let top = (index / 4) * CELL_SIZE;
+            let held = keypad.is_held(*key);
+            let glyph = &FONT[*key as usize * 5..*key as usize * 5 + 5];
+
+            for y in 0..CELL_SIZE {
+                for x in 0..CELL_SIZE {
+                    // The 4x5 glyph of the key is drawn in the middle of the cell, with the marker below it
+                    let in_glyph = (2..6).contains(&x)
+                        && (1..6).contains(&y)
+                        && (glyph[y - 1] >> (7 - (x - 2))) & 1 == 1;
+                    let color = if x == 0 || y == 0 {
+                        match self.waiting {
+                            true => GRID_WAITING,
+                            false => GRID,
+                        }
+                    } else if in_glyph {
+                        match held {
+                            true => BACKGROUND,
+                            false => GLYPH,
+                        }
+                    } else if y == 7 && (2..6).contains(&x) && self.keys_read & (1 << key) != 0 {
+                        READ_MARKER
+                    } else {
+                        match held {
+                            true => HELD,
+                            false => BACKGROUND,
+                        }
+                    };
+                    let offset = ((top + y) * frame_width + left + x) * 4;
+
+                    frame[offset..offset + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{fmt, str::FromStr};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{cli::PaletteArgs, config::DisplayConfig};
+
+// An RGB color, written as `#RRGGBB` in the configuration and on the command line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.trim_start_matches('#');
+        let invalid = || format!("invalid color `{}`, expected #RRGGBB", value);
+
+        if hex.len() != 6 {
+            return Err(invalid());
+        }
+
+        let rgb = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+
+        Ok(Color([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]))
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+// The colors are indexed by the value of a pixel in the VRAM: 0 is the background and 1 the foreground. XO-CHIP games
+// draw on two bit planes and use four colors, so every palette defines two more colors for them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[(pixel & 0x03) as usize]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    /// White on black
+    Classic,
+
+    /// Green phosphor of the old monochrome monitors
+    GreenPhosphor,
+
+    /// Amber phosphor of the old monochrome monitors
+    Amber,
+
+    /// Dark green on the pale green of early LCD handhelds
+    Lcd,
+
+    /// Default colors of the Octo IDE
+    Octo,
+
+    /// Black on white
+    HighContrast,
+}
+
+const THEMES: [Theme; 6] = [
+    Theme::Classic,
+    Theme::GreenPhosphor,
+    Theme::Amber,
+    Theme::Lcd,
+    Theme::Octo,
+    Theme::HighContrast,
+];
+
+impl Theme {
+    pub fn palette(&self) -> Palette {
+        let colors = match self {
+            Theme::Classic => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            Theme::GreenPhosphor => [0x0A1A0A, 0x33FF33, 0x1F9F1F, 0x145214],
+            Theme::Amber => [0x1A0F00, 0xFFB000, 0xB37B00, 0x664600],
+            Theme::Lcd => [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F],
+            Theme::Octo => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+            Theme::HighContrast => [0xFFFFFF, 0x000000, 0xFF0000, 0x0000FF],
+        };
+
+        Palette {
+            colors: colors.map(|rgb: u32| Color([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])),
+        }
+    }
+
+    // The theme following this one, to cycle through all of them at runtime.
+    pub fn next(&self) -> Theme {
+        let index = THEMES.iter().position(|theme| theme == self).unwrap_or(0);
+
+        THEMES[(index + 1) % THEMES.len()]
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+// Colors given on the command line take precedence over the colors of the ROM in the database, which take precedence
+// over the configuration file, which takes precedence over the theme.
+pub fn resolve(
+    args: &PaletteArgs,
+    config: &DisplayConfig,
+    rom_colors: &[Color],
+) -> (Theme, Palette) {
+    let theme = args.palette.or(config.palette).unwrap_or(Theme::Classic);
+    let mut palette = theme.palette();
+
+    if let Some(colors) = config.colors {
+        palette.colors = colors;
+    }
+
+    // The colors of a ROM found in the database replace the configured ones, but not a theme given on the command line
+    if args.palette.is_none() {
+        for (color, rom_color) in palette.colors.iter_mut().zip(rom_colors) {
+            *color = *rom_color;
+        }
+    }
+
+    if let Some(background) = args.background.or(config.background) {
+        palette.colors[0] = background;
+    }
+
+    if let Some(foreground) = args.foreground.or(config.foreground) {
+        palette.colors[1] = foreground;
+    }
+
+    (theme, palette)
+}
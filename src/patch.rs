@@ -0,0 +1,558 @@
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use crate::{
+    cli::{PatchArgs, PatchCommand},
+    cpu::MAX_ROM_SIZE,
+};
+
+// The shortest match worth a BPS copy action rather than a literal, as a copy takes a couple of bytes to encode.
+const MIN_COPY_LENGTH: usize = 4;
+
+// The offsets of the original where the same bytes start that are tried for a copy. When the bytes are repeated a lot,
+// e.g. zeros, the first offsets are enough.
+const MAX_COPY_CANDIDATES: usize = 64;
+
+// IPS records hold at most this many bytes, and cannot start at the offset spelling `EOF`.
+const IPS_MAX_RECORD: usize = 0xFFFF;
+const IPS_EOF: &[u8] = b"EOF";
+
+// A patch can't be trusted with the size of the patched ROM, so it's checked before the ROM is allocated.
+const TOO_LARGE: &str = "the patched ROM doesn't fit in the memory";
+
+pub fn run(args: PatchArgs) -> Result<(), Box<dyn Error>> {
+    match args.command {
+        PatchCommand::Create(args) => {
+            let original = crate::load_rom(&args.original_path)?;
+            let modified = crate::load_rom(&args.modified_path)?;
+            let patch = match extension(&args.output).as_str() {
+                "ips" => create_ips(&original, &modified)?,
+                _ => create_bps(&original, &modified),
+            };
+
+            fs::write(&args.output, &patch)
+                .map_err(|error| format!("Cannot write {}: {}", args.output.display(), error))?;
+            println!(
+                "Patch of {} bytes written to {}",
+                patch.len(),
+                args.output.display()
+            );
+
+            Ok(())
+        }
+    }
+}
+
+// Apply an IPS or a BPS patch to a ROM, the format being told by the header of the patch.
+pub fn apply(rom: &[u8], patch_path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let patch = fs::read(patch_path)
+        .map_err(|error| format!("Cannot read {}: {}", patch_path.display(), error))?;
+    let patched = if patch.starts_with(b"PATCH") {
+        apply_ips(rom, &patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, &patch)
+    } else {
+        Err("unknown format, expected an IPS or a BPS patch".to_string())
+    };
+
+    patched.map_err(|error| format!("Cannot apply {}: {}", patch_path.display(), error).into())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// IPS patches are a list of records writing bytes at an offset, either given or repeated (RLE), between a `PATCH`
+// header and an `EOF` footer, optionally followed by the size to truncate the ROM to. All numbers are big-endian.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut rom = rom.to_vec();
+    let mut reader = Reader::new(&patch[5..]);
+
+    loop {
+        if reader.remaining().starts_with(IPS_EOF) {
+            reader.bytes(IPS_EOF.len())?;
+            break;
+        }
+
+        let offset = reader.big_endian(3)?;
+        let size = reader.big_endian(2)?;
+        let (bytes, size) = match size {
+            0 => {
+                let size = reader.big_endian(2)?;
+                let value = reader.bytes(1)?[0];
+
+                (vec![value; size], size)
+            }
+            _ => (reader.bytes(size)?.to_vec(), size),
+        };
+
+        if offset + size > MAX_ROM_SIZE {
+            return Err(TOO_LARGE.to_string());
+        }
+
+        if rom.len() < offset + size {
+            rom.resize(offset + size, 0);
+        }
+
+        rom[offset..offset + size].copy_from_slice(&bytes);
+    }
+
+    match reader.remaining().len() {
+        0 => {}
+        3 => rom.truncate(reader.big_endian(3)?),
+        _ => return Err("unexpected data after the end of the patch".to_string()),
+    }
+
+    Ok(rom)
+}
+
+fn create_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, String> {
+    if modified.len() > 0xFFFFFF {
+        return Err("the ROM is too large for an IPS patch".to_string());
+    }
+
+    let mut patch = b"PATCH".to_vec();
+    let mut offset = 0;
+
+    while offset < modified.len() {
+        if original.get(offset) == Some(&modified[offset]) {
+            offset += 1;
+            continue;
+        }
+
+        // A record covers the differing bytes, and the bytes past the end of the original
+        let mut end = offset;
+
+        while end < modified.len()
+            && end - offset < IPS_MAX_RECORD
+            && original.get(end) != Some(&modified[end])
+        {
+            end += 1;
+        }
+
+        // An offset reading `EOF` would end the patch, so the record starts a byte earlier
+        let start = match three_bytes(offset) == IPS_EOF {
+            true => offset - 1,
+            false => offset,
+        };
+
+        patch.extend_from_slice(&three_bytes(start));
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[start..end]);
+        offset = end;
+    }
+
+    patch.extend_from_slice(IPS_EOF);
+
+    if modified.len() < original.len() {
+        patch.extend_from_slice(&three_bytes(modified.len()));
+    }
+
+    Ok(patch)
+}
+
+// The offsets and the sizes of IPS patches are 24-bit numbers.
+fn three_bytes(number: usize) -> [u8; 3] {
+    let [_, bytes @ ..] = (number as u32).to_be_bytes();
+
+    bytes
+}
+
+// BPS patches build the modified ROM from actions copying bytes from the original, from the patch or from the part of
+// the modified ROM already built. The footer holds the CRC32 of the original, of the modified ROM and of the patch, so
+// that a patch isn't applied to the wrong ROM. Numbers are variable-length, 7 bits per byte.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 4 + 12 {
+        return Err("the patch is truncated".to_string());
+    }
+
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let checksum =
+        |index: usize| u32::from_le_bytes(footer[index * 4..index * 4 + 4].try_into().unwrap());
+
+    if crc32fast::hash(&patch[..patch.len() - 4]) != checksum(2) {
+        return Err("the patch is corrupted".to_string());
+    }
+
+    if crc32fast::hash(rom) != checksum(0) {
+        return Err("the patch is for another ROM".to_string());
+    }
+
+    let mut reader = Reader::new(&body[4..]);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err("the patch is for another ROM".to_string());
+    }
+
+    if target_size > MAX_ROM_SIZE {
+        return Err(TOO_LARGE.to_string());
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0, 0);
+
+    while !reader.remaining().is_empty() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        let out_of_bounds = || "an action reads out of bounds".to_string();
+
+        if target.len() + length > target_size {
+            return Err("an action writes past the end of the patched ROM".to_string());
+        }
+
+        match action & 3 {
+            // Source read: copy the bytes of the original at the same offset
+            0 => target.extend_from_slice(
+                rom.get(target.len()..target.len() + length)
+                    .ok_or_else(out_of_bounds)?,
+            ),
+
+            // Target read: copy the bytes of the patch
+            1 => target.extend_from_slice(reader.bytes(length)?),
+
+            // Source copy: copy the bytes of the original from anywhere
+            2 => {
+                source_offset =
+                    relative_offset(source_offset, reader.number()?).ok_or_else(out_of_bounds)?;
+                target.extend_from_slice(
+                    rom.get(source_offset..source_offset + length)
+                        .ok_or_else(out_of_bounds)?,
+                );
+                source_offset += length;
+            }
+
+            // Target copy: copy the bytes already written one by one, as they can overlap with the ones being written
+            _ => {
+                target_offset =
+                    relative_offset(target_offset, reader.number()?).ok_or_else(out_of_bounds)?;
+
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or_else(out_of_bounds)?;
+
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size || crc32fast::hash(&target) != checksum(1) {
+        return Err("the patched ROM doesn't match the checksum of the patch".to_string());
+    }
+
+    Ok(target)
+}
+
+fn create_bps(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = b"BPS1".to_vec();
+    let mut literal = Vec::new();
+    let mut source_offset = 0;
+    let mut position = 0;
+
+    write_number(&mut patch, original.len());
+    write_number(&mut patch, modified.len());
+    write_number(&mut patch, 0);
+
+    // The offsets at which every sequence of `MIN_COPY_LENGTH` bytes starts in the original, so that a match doesn't
+    // have to be looked for at every offset
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+
+    for (offset, bytes) in original.windows(MIN_COPY_LENGTH).enumerate() {
+        let offsets = index.entry(bytes).or_default();
+
+        if offsets.len() < MAX_COPY_CANDIDATES {
+            offsets.push(offset);
+        }
+    }
+
+    while position < modified.len() {
+        // The bytes at the same offset in the original, or else the longest match anywhere in it, preferring the end of
+        // the last copy, which is the cheapest offset to encode
+        let same = common_length(
+            original.get(position..).unwrap_or(&[]),
+            &modified[position..],
+        );
+        let candidates = modified
+            .get(position..position + MIN_COPY_LENGTH)
+            .and_then(|bytes| index.get(bytes))
+            .into_iter()
+            .flatten()
+            .copied()
+            .chain(Some(source_offset).filter(|offset| *offset < original.len()));
+        let (offset, length) = candidates
+            .map(|offset| {
+                (
+                    offset,
+                    common_length(&original[offset..], &modified[position..]),
+                )
+            })
+            .max_by_key(|(offset, length)| (*length, *offset == source_offset))
+            .unwrap_or((0, 0));
+
+        if same.max(length) < MIN_COPY_LENGTH && !(same > 0 && literal.is_empty()) {
+            literal.push(modified[position]);
+            position += 1;
+            continue;
+        }
+
+        write_literal(&mut patch, &mut literal);
+
+        if same >= length {
+            write_number(&mut patch, (same - 1) << 2);
+            position += same;
+        } else {
+            let relative = offset as isize - source_offset as isize;
+
+            write_number(&mut patch, (length - 1) << 2 | 2);
+            write_number(
+                &mut patch,
+                (relative.unsigned_abs() << 1) | (relative < 0) as usize,
+            );
+            source_offset = offset + length;
+            position += length;
+        }
+    }
+
+    write_literal(&mut patch, &mut literal);
+    patch.extend_from_slice(&crc32fast::hash(original).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(modified).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(&patch).to_le_bytes());
+
+    patch
+}
+
+fn write_literal(patch: &mut Vec<u8>, literal: &mut Vec<u8>) {
+    if !literal.is_empty() {
+        write_number(patch, (literal.len() - 1) << 2 | 1);
+        patch.append(literal);
+    }
+}
+
+// Numbers are written 7 bits at a time, the last byte having its high bit set. One is subtracted after each byte so
+// that every number has a single encoding.
+fn write_number(patch: &mut Vec<u8>, mut number: usize) {
+    loop {
+        let bits = (number & 0x7F) as u8;
+
+        number >>= 7;
+
+        if number == 0 {
+            patch.push(0x80 | bits);
+            break;
+        }
+
+        patch.push(bits);
+        number -= 1;
+    }
+}
+
+// Offsets are relative to the end of the last copy, the lowest bit being the sign.
+fn relative_offset(offset: usize, encoded: usize) -> Option<usize> {
+    match encoded & 1 {
+        0 => offset.checked_add(encoded >> 1),
+        _ => offset.checked_sub(encoded >> 1),
+    }
+}
+
+fn common_length(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+// Read the fields of a patch, failing when it's truncated.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if count > self.data.len() {
+            return Err("the patch is truncated".to_string());
+        }
+
+        let (bytes, rest) = self.data.split_at(count);
+
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    fn big_endian(&mut self, size: usize) -> Result<usize, String> {
+        let bytes = self.bytes(size)?;
+
+        Ok(bytes
+            .iter()
+            .fold(0, |number, byte| number << 8 | *byte as usize))
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+
+        loop {
+            let byte = self.bytes(1)?[0] as usize;
+
+            number = (byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| number.checked_add(bits))
+                .ok_or("a number is too large")?;
+
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+
+            shift = shift
+                .checked_shl(7)
+                .filter(|shift| *shift <= 1 << 32)
+                .ok_or("a number is too large")?;
+            number += shift;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original() -> Vec<u8> {
+        (0..=255).cycle().take(600).collect()
+    }
+
+    // A modified ROM with changed, moved and appended bytes
+    fn modified() -> Vec<u8> {
+        let mut modified = original();
+
+        modified[10] = 0xAA;
+        modified[300..340].fill(0x55);
+        modified.copy_within(0..100, 400);
+        modified.extend_from_slice(b"appended");
+
+        modified
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let patch = create_ips(&original(), &modified()).unwrap();
+
+        assert_eq!(apply_ips(&original(), &patch).unwrap(), modified());
+    }
+
+    #[test]
+    fn bps_round_trip() {
+        let patch = create_bps(&original(), &modified());
+
+        assert_eq!(apply_bps(&original(), &patch).unwrap(), modified());
+    }
+
+    #[test]
+    fn ips_truncation() {
+        let truncated = original()[..500].to_vec();
+        let patch = create_ips(&original(), &truncated).unwrap();
+
+        assert_eq!(apply_ips(&original(), &patch).unwrap(), truncated);
+    }
+
+    #[test]
+    fn bps_truncation() {
+        let truncated = original()[..500].to_vec();
+        let patch = create_bps(&original(), &truncated);
+
+        assert_eq!(apply_bps(&original(), &patch).unwrap(), truncated);
+    }
+
+    #[test]
+    fn ips_eof_offset() {
+        // A record can't start at the offset spelling `EOF`, so it starts a byte earlier
+        let original = vec![0; 0x454F47];
+        let mut modified = original.clone();
+
+        modified[0x454F46] = 1;
+
+        let patch = create_ips(&original, &modified).unwrap();
+
+        assert_eq!(&patch[5..10], &[0x45, 0x4F, 0x45, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn ips_rle_record() {
+        let mut patch = b"PATCH".to_vec();
+
+        // 4 bytes of 0x77 at offset 2
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x77]);
+        patch.extend_from_slice(IPS_EOF);
+
+        assert_eq!(
+            apply_ips(&[1, 2, 3], &patch).unwrap(),
+            [1, 2, 0x77, 0x77, 0x77, 0x77]
+        );
+    }
+
+    #[test]
+    fn bps_wrong_rom() {
+        let patch = create_bps(&original(), &modified());
+        let mut other = original();
+
+        other[0] ^= 1;
+
+        assert_eq!(
+            apply_bps(&other, &patch).unwrap_err(),
+            "the patch is for another ROM"
+        );
+    }
+
+    #[test]
+    fn bps_corrupted_patch() {
+        let mut patch = create_bps(&original(), &modified());
+
+        patch[5] ^= 1;
+
+        assert_eq!(
+            apply_bps(&original(), &patch).unwrap_err(),
+            "the patch is corrupted"
+        );
+    }
+
+    #[test]
+    fn patched_rom_too_large() {
+        let large = vec![0; MAX_ROM_SIZE + 1];
+
+        assert_eq!(
+            apply_ips(&[], &create_ips(&[], &large).unwrap()).unwrap_err(),
+            TOO_LARGE
+        );
+        assert_eq!(
+            apply_bps(&[], &create_bps(&[], &large)).unwrap_err(),
+            TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn bps_copies() {
+        // Bytes that don't repeat, with a block moved elsewhere and a run of zeros
+        let original: Vec<u8> = (0..MAX_ROM_SIZE as u32)
+            .map(|index| (index.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut modified = original.clone();
+
+        modified.copy_within(100..1100, 2000);
+        modified[1200..1500].fill(0);
+
+        let patch = create_bps(&original, &modified);
+
+        assert_eq!(apply_bps(&original, &patch).unwrap(), modified);
+        assert!(patch.len() < 400, "{} bytes", patch.len());
+    }
+}
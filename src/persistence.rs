@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+// Fraction of its brightness lost by a pixel at every frame (1/60 s) once it's turned off, in `fade` mode.
+pub const DEFAULT_DECAY_RATE: f32 = 0.4;
+
+// Below this brightness, a fading pixel cannot be told apart from the background anymore.
+const MIN_BRIGHTNESS: f32 = 1.0 / 255.0;
+
+const FRAMES_PER_SECOND: f32 = 60.0;
+
+// Games erase a sprite by drawing it again with XOR before drawing it at its new position, so moving sprites are off
+// for a part of the time and flicker. Keeping the pixels lit for a while after they're turned off hides it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Persistence {
+    /// Pixels are turned off immediately
+    None,
+
+    /// Pixels fade out exponentially, like the phosphor of a CRT
+    Fade,
+
+    /// Pixels stay lit for one more frame, showing the OR of the last two frames
+    Blend,
+}
+
+// Post-process the VRAM before it's drawn to the frame buffer: keep track of how long every pixel has been off and
+// compute its brightness, from 0 (background) to 1 (foreground).
+pub struct Renderer {
+    persistence: Persistence,
+    decay_rate: f32,
+
+    // Number of frames (possibly fractional) elapsed since every pixel was turned off, 0 if it's on.
+    ages: Vec<f32>,
+    brightness: Vec<f32>,
+    last_update: Instant,
+}
+
+impl Renderer {
+    pub fn new(persistence: Persistence, decay_rate: f32, pixel_count: usize) -> Renderer {
+        Renderer {
+            persistence,
+            decay_rate,
+            ages: vec![f32::INFINITY; pixel_count],
+            brightness: vec![0.0; pixel_count],
+            last_update: Instant::now(),
+        }
+    }
+
+    // Age the pixels that are off in the given VRAM. It must be called with the current VRAM before it's replaced by a
+    // new frame, so that the pixels turned off by that frame start fading at that time, and before drawing. Return
+    // `true` while some pixels are still fading, in which case the screen should be redrawn again.
+    pub fn update(&mut self, vram: &[u8]) -> bool {
+        let now = Instant::now();
+        let frames = (now - self.last_update).as_secs_f32() * FRAMES_PER_SECOND;
+        let mut fading = false;
+
+        self.last_update = now;
+
+        for ((age, brightness), p) in self.ages.iter_mut().zip(&mut self.brightness).zip(vram) {
+            *age = match p {
+                0 => *age + frames,
+                1 => 0.0,
+                _ => panic!("Invalid VRAM value ({})", p),
+            };
+            *brightness = self.persistence.brightness(*age, self.decay_rate);
+            fading |= *p == 0 && *brightness > 0.0;
+        }
+
+        fading
+    }
+
+    pub fn brightness(&self) -> &[f32] {
+        &self.brightness
+    }
+}
+
+impl Persistence {
+    // Brightness of a pixel turned off `age` frames ago.
+    fn brightness(&self, age: f32, decay_rate: f32) -> f32 {
+        let brightness = match self {
+            _ if age == 0.0 => 1.0,
+            Persistence::None => 0.0,
+            Persistence::Fade => (1.0 - decay_rate).powf(age),
+            Persistence::Blend if age <= 1.0 => 1.0,
+            Persistence::Blend => 0.0,
+        };
+
+        if brightness < MIN_BRIGHTNESS {
+            0.0
+        } else {
+            brightness
+        }
+    }
+}
+
+// Parse a decay rate, which must be greater than 0 (pixels would never fade out) and at most 1 (pixels are turned off
+// immediately).
+pub fn parse_decay_rate(value: &str) -> Result<f32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|rate| *rate > 0.0 && *rate <= 1.0)
+        .ok_or_else(|| {
+            format!(
+                "invalid decay rate `{}`, expected a number in ]0, 1]",
+                value
+            )
+        })
+}
@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{cpu::ROM_ADDRESS, nibble::Nibble};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Platform {
+    Vip,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Vip => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+// An instruction telling the platform a ROM was written for, along with where it was first found and how many times.
+pub struct Clue {
+    pub platform: Platform,
+    pub meaning: &'static str,
+    pub address: u16,
+    pub instruction: u16,
+    pub count: usize,
+}
+
+// The platform a ROM was written for, guessed from its instructions when it's not known from the settings or the
+// database.
+pub struct Detection {
+    pub platform: Platform,
+    pub clues: Vec<Clue>,
+}
+
+impl Detection {
+    // The XO-CHIP isn't supported, and its quirks are the ones of the CHIP-8, so its ROMs are run as the CHIP-8.
+    pub fn super_chip(&self) -> bool {
+        self.platform == Platform::SuperChip
+    }
+
+    // Explain how the platform was chosen, one line per clue.
+    pub fn report(&self) -> String {
+        let mut report = match self.clues.is_empty() {
+            true => "No instruction specific to a platform was found, running as the CHIP-8\n"
+                .to_string(),
+            false => format!(
+                "Running as the {}, from the instructions of the ROM:\n",
+                match self.platform {
+                    Platform::XoChip => "CHIP-8 (the XO-CHIP isn't supported)",
+                    platform => platform.name(),
+                }
+            ),
+        };
+
+        for clue in &self.clues {
+            report.push_str(&format!(
+                "  {:03X}: {:04X} {} ({}){}\n",
+                clue.address,
+                clue.instruction,
+                clue.meaning,
+                clue.platform.name(),
+                match clue.count {
+                    1 => String::new(),
+                    count => format!(", found {} times", count),
+                }
+            ));
+        }
+
+        report
+    }
+}
+
+// ROMs mix code with data such as sprites, and data looks like instructions of every platform. So rather than reading
+// the ROM as a whole, the instructions are found by following the flow of the program from its start, through the
+// jumps, the calls and both sides of the skips. Computed jumps (BNNN) can't be followed, so the code only reached
+// through them is missed.
+//
+// The instructions of the XO-CHIP are the strongest clue, as it extends the SUPER-CHIP, then the ones of the
+// SUPER-CHIP. Otherwise the ROM is run as the CHIP-8, which is also what shifts from VY and calls to machine code of the
+// COSMAC VIP suggest.
+pub fn detect(rom: &[u8]) -> Detection {
+    let mut clues: BTreeMap<&'static str, Clue> = BTreeMap::new();
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![ROM_ADDRESS];
+    let end = ROM_ADDRESS as usize + rom.len();
+    let instruction_at = |address: u16| {
+        let offset = (address - ROM_ADDRESS) as usize;
+
+        u16::from_be_bytes([rom[offset], rom[offset + 1]])
+    };
+
+    while let Some(address) = pending.pop() {
+        if address < ROM_ADDRESS || address as usize + 1 >= end || !visited.insert(address) {
+            continue;
+        }
+
+        let instruction = instruction_at(address);
+
+        if let Some((platform, meaning)) = clue(instruction) {
+            clues
+                .entry(meaning)
+                .and_modify(|clue| clue.count += 1)
+                .or_insert(Clue {
+                    platform,
+                    meaning,
+                    address,
+                    instruction,
+                    count: 1,
+                });
+        }
+
+        let next = address + 2;
+
+        match instruction.get_nibbles() {
+            // Return, exit (SUPER-CHIP) and computed jump
+            (0x0, 0x0, 0xE, 0xE) | (0x0, 0x0, 0xF, 0xD) | (0xB, _, _, _) => {}
+            (0x1, _, _, _) => pending.push(instruction & 0x0FFF),
+            (0x2, _, _, _) => pending.extend([instruction & 0x0FFF, next]),
+
+            // Skips, over a single instruction even if it's a 4-byte long load (XO-CHIP)
+            (0x3 | 0x4, _, _, _)
+            | (0x5 | 0x9, _, _, 0x0)
+            | (0xE, _, 0x9, 0xE)
+            | (0xE, _, 0xA, 0x1) => {
+                let skipped = match (next as usize + 1) < end && instruction_at(next) == 0xF000 {
+                    true => 4,
+                    false => 2,
+                };
+
+                pending.extend([next, next + skipped])
+            }
+
+            // Long load of I (XO-CHIP), followed by the address
+            (0xF, 0x0, 0x0, 0x0) => pending.push(next + 2),
+            _ => pending.push(next),
+        }
+    }
+
+    let mut clues: Vec<Clue> = clues.into_values().collect();
+
+    clues.sort_by_key(|clue| clue.address);
+    let platform = clues
+        .iter()
+        .map(|clue| clue.platform)
+        .max()
+        .unwrap_or(Platform::Vip);
+
+    Detection { platform, clues }
+}
+
+fn clue(instruction: u16) -> Option<(Platform, &'static str)> {
+    let clue = match instruction.get_nibbles() {
+        (0x0, 0x0, 0xF, 0xF) => (Platform::SuperChip, "switches to the high resolution"),
+        (0x0, 0x0, 0xF, 0xE) => (Platform::SuperChip, "switches to the low resolution"),
+        (0x0, 0x0, 0xF, 0xB) | (0x0, 0x0, 0xF, 0xC) | (0x0, 0x0, 0xC, _) => {
+            (Platform::SuperChip, "scrolls the screen")
+        }
+        (0x0, 0x0, 0xF, 0xD) => (Platform::SuperChip, "exits the interpreter"),
+        (0xD, _, _, 0x0) => (Platform::SuperChip, "draws a 16x16 sprite"),
+        (0xF, _, 0x3, 0x0) => (Platform::SuperChip, "points I to a large font character"),
+        (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => {
+            (Platform::SuperChip, "saves or restores the flag registers")
+        }
+        (0x0, 0x0, 0xD, _) => (Platform::XoChip, "scrolls the screen up"),
+        (0xF, 0x0, 0x0, 0x0) => (Platform::XoChip, "loads I with a 16-bit address"),
+        (0xF, _, 0x0, 0x1) => (Platform::XoChip, "selects the bit planes to draw on"),
+        (0xF, 0x0, 0x0, 0x2) => (Platform::XoChip, "loads an audio pattern"),
+        (0xF, _, 0x3, 0xA) => (Platform::XoChip, "sets the pitch of the sound"),
+        (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => {
+            (Platform::XoChip, "saves or loads a range of registers")
+        }
+        (0x0, 0x0, 0xE, 0x0) | (0x0, 0x0, 0xE, 0xE) => return None,
+        (0x0, _, _, _) => (Platform::Vip, "calls machine code of the COSMAC VIP"),
+
+        // The SUPER-CHIP ignores VY, so giving another register only makes sense on the COSMAC VIP
+        (0x8, x, y, 0x6) | (0x8, x, y, 0xE) if x != y => (Platform::Vip, "shifts VY into VX"),
+        _ => return None,
+    };
+
+    Some(clue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The meanings of the clues found in a ROM given as instructions, and the platform guessed from them.
+    fn detect_words(words: &[u16]) -> (Platform, Vec<&'static str>) {
+        let rom: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let detection = detect(&rom);
+
+        (
+            detection.platform,
+            detection.clues.iter().map(|clue| clue.meaning).collect(),
+        )
+    }
+
+    #[test]
+    fn no_clue() {
+        assert_eq!(detect_words(&[0x6005, 0x1202]), (Platform::Vip, vec![]));
+        assert!(detect(&[0x60, 0x05]).report().starts_with("No instruction"));
+    }
+
+    #[test]
+    fn super_chip() {
+        assert_eq!(
+            detect_words(&[0x00FF, 0xD120, 0x1204]),
+            (
+                Platform::SuperChip,
+                vec!["switches to the high resolution", "draws a 16x16 sprite"]
+            )
+        );
+    }
+
+    #[test]
+    fn xo_chip() {
+        // The long load of I is a stronger clue than the high resolution
+        assert_eq!(
+            detect_words(&[0x00FF, 0xF000, 0x0300, 0x1206]),
+            (
+                Platform::XoChip,
+                vec![
+                    "switches to the high resolution",
+                    "loads I with a 16-bit address"
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn vip() {
+        assert_eq!(
+            detect_words(&[0x0123, 0x8126, 0x8116, 0x1206]),
+            (
+                Platform::Vip,
+                vec!["calls machine code of the COSMAC VIP", "shifts VY into VX"]
+            )
+        );
+    }
+
+    #[test]
+    fn clue_count() {
+        let detection = detect(&[0x00, 0xFF, 0x00, 0xFF, 0x12, 0x04]);
+
+        assert_eq!(detection.clues.len(), 1);
+        assert_eq!(
+            (detection.clues[0].address, detection.clues[0].count),
+            (0x200, 2)
+        );
+        assert!(detection.report().contains("found 2 times"));
+    }
+
+    #[test]
+    fn data_after_jump() {
+        // The sprite after the loop reads as a switch to the high resolution
+        assert_eq!(detect_words(&[0x1200, 0x00FF]), (Platform::Vip, vec![]));
+    }
+
+    #[test]
+    fn data_after_return_and_exit() {
+        assert_eq!(
+            detect_words(&[0x2206, 0x1202, 0x0000, 0x00EE, 0x00FF]),
+            (Platform::Vip, vec![])
+        );
+        assert_eq!(
+            detect_words(&[0x00FD, 0x00FF]),
+            (Platform::SuperChip, vec!["exits the interpreter"])
+        );
+    }
+
+    #[test]
+    fn both_sides_of_skips() {
+        assert_eq!(
+            detect_words(&[0x3000, 0x1206, 0x00FF, 0x1206]),
+            (Platform::SuperChip, vec!["switches to the high resolution"])
+        );
+    }
+
+    #[test]
+    fn skip_over_long_load() {
+        // The skip jumps over the 4 bytes of the long load, whose address isn't an instruction
+        assert_eq!(
+            detect_words(&[0x3000, 0xF000, 0x00FF, 0x1206]),
+            (Platform::XoChip, vec!["loads I with a 16-bit address"])
+        );
+    }
+
+    #[test]
+    fn computed_jump_not_followed() {
+        assert_eq!(
+            detect_words(&[0xB204, 0x0000, 0x00FF]),
+            (Platform::Vip, vec![])
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    cpu::{Cpu, ROM_ADDRESS},
+    operation::Operation,
+    symbols::Symbols,
+};
+
+const TOP_ADDRESSES: usize = 20;
+
+// Every byte of the program is flagged with the way it has been accessed while profiling.
+const EXECUTED: u8 = 0b01;
+const READ_AS_SPRITE: u8 = 0b10;
+
+pub struct Profiler {
+    rom_size: usize,
+    instructions: u64,
+
+    // Number of executions per address and per operation variant.
+    executions: HashMap<u16, u64>,
+    operations: HashMap<&'static str, u64>,
+
+    // Number of iterations of each loop, identified by the target and the origin of a backward jump.
+    loops: HashMap<(u16, u16), u64>,
+
+    // Number of calls per subroutine address.
+    calls: HashMap<u16, u64>,
+
+    accesses: Vec<u8>,
+
+    // Labels of the program, shown next to the addresses.
+    symbols: Symbols,
+}
+
+impl Profiler {
+    pub fn new(rom_size: usize, symbols: Symbols) -> Profiler {
+        Profiler {
+            rom_size,
+            instructions: 0,
+            executions: HashMap::new(),
+            operations: HashMap::new(),
+            loops: HashMap::new(),
+            calls: HashMap::new(),
+            accesses: vec![0; ROM_ADDRESS as usize + rom_size],
+            symbols,
+        }
+    }
+
+    // Record the instruction the CPU is about to execute.
+    pub fn record(&mut self, cpu: &Cpu) {
+        let pc = cpu.pc();
+        let operation = cpu.peek();
+
+        self.instructions += 1;
+        *self.executions.entry(pc).or_insert(0) += 1;
+        *self.operations.entry(operation.name()).or_insert(0) += 1;
+        self.mark(pc, 2, EXECUTED);
+
+        match operation {
+            Operation::JumpTo { address } if address <= pc => {
+                *self.loops.entry((address, pc)).or_insert(0) += 1;
+            }
+            Operation::CallSubroutineAt { address } => {
+                *self.calls.entry(address).or_insert(0) += 1;
+            }
+            Operation::DrawSpriteAt { height, .. } => {
+                self.mark(cpu.i(), height as u16, READ_AS_SPRITE);
+            }
+            _ => {}
+        }
+    }
+
+    fn mark(&mut self, address: u16, length: u16, access: u8) {
+        for address in address..address.saturating_add(length) {
+            if let Some(flags) = self.accesses.get_mut(address as usize) {
+                *flags |= access;
+            }
+        }
+    }
+
+    pub fn write_report(&self, cpu: &Cpu, path: &Path) -> io::Result<()> {
+        let mut report = String::new();
+
+        // Writing to a String cannot fail.
+        self.format_report(cpu, &mut report)
+            .expect("Cannot format the profile report");
+
+        fs::write(path, report)
+    }
+
+    fn format_report(&self, cpu: &Cpu, report: &mut String) -> fmt::Result {
+        writeln!(report, "Instructions executed: {}", self.instructions)?;
+
+        writeln!(report, "\nOperations")?;
+        for (name, count) in sorted_by_count(&self.operations) {
+            writeln!(report, "  {:>10}  {}", count, name)?;
+        }
+
+        writeln!(report, "\nHot addresses")?;
+        for (address, count) in sorted_by_count(&self.executions).take(TOP_ADDRESSES) {
+            writeln!(
+                report,
+                "  {:>10}  {}  {}",
+                count,
+                self.symbols.annotate(*address),
+                disassemble(&self.symbols, cpu, *address)
+            )?;
+        }
+
+        writeln!(report, "\nHot loops")?;
+        for ((start, end), count) in sorted_by_count(&self.loops) {
+            writeln!(
+                report,
+                "  {:>10}  {} - {}",
+                count,
+                self.symbols.annotate(*start),
+                self.symbols.annotate(*end)
+            )?;
+        }
+
+        writeln!(report, "\nSubroutine calls")?;
+        for (address, count) in sorted_by_count(&self.calls) {
+            writeln!(
+                report,
+                "  {:>10}  {}",
+                count,
+                self.symbols.annotate(*address)
+            )?;
+        }
+
+        // Executed instructions are disassembled, bytes drawn as sprites are shown as pixels and anything else is
+        // reported as data that has never been touched.
+        writeln!(
+            report,
+            "\nAnnotated disassembly (X: executed, S: read as sprite, .: never touched)"
+        )?;
+        let mut address = ROM_ADDRESS as usize;
+
+        while address < ROM_ADDRESS as usize + self.rom_size {
+            let flags = self.accesses[address];
+            let byte = cpu.ram()[address];
+
+            if let Some(label) = self.symbols.label_at(address as u16) {
+                writeln!(report, "{}:", label)?;
+            }
+
+            if flags & EXECUTED != 0 {
+                let count = self.executions.get(&(address as u16)).copied().unwrap_or(0);
+                writeln!(
+                    report,
+                    "  0x{:03X}  {:02X}{:02X}  X{}  {:>10}  {}",
+                    address,
+                    byte,
+                    cpu.ram()[address + 1],
+                    if flags & READ_AS_SPRITE != 0 {
+                        'S'
+                    } else {
+                        ' '
+                    },
+                    count,
+                    disassemble(&self.symbols, cpu, address as u16)
+                )?;
+                address += 2;
+            } else {
+                let pixels: String = (0..8)
+                    .map(|bit| {
+                        if (byte >> (7 - bit)) & 1 == 1 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                writeln!(
+                    report,
+                    "  0x{:03X}  {:02X}    {}               {}",
+                    address,
+                    byte,
+                    if flags & READ_AS_SPRITE != 0 {
+                        "S "
+                    } else {
+                        ". "
+                    },
+                    pixels
+                )?;
+                address += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn sorted_by_count<K: Ord>(counts: &HashMap<K, u64>) -> impl Iterator<Item = (&K, &u64)> {
+    let mut entries: Vec<_> = counts.iter().collect();
+
+    entries.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    entries.into_iter()
+}
+
+// Self-modifying code may have overwritten an executed instruction with something that doesn't decode.
+fn disassemble(symbols: &Symbols, cpu: &Cpu, address: u16) -> String {
+    match cpu.try_peek_at(address) {
+        Some(operation) => symbols.disassemble(operation),
+        None => "???".to_string(),
+    }
+}
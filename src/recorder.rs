@@ -0,0 +1,308 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{palette::Palette, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+// The delay between two frames of a GIF is given in hundredths of a second.
+const GIF_TIME_UNITS_PER_SECOND: u64 = 100;
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE as u64 / FRAMES_PER_SECOND) as usize;
+const BEEP_FREQUENCY: f32 = 440.0;
+const BEEP_AMPLITUDE: f32 = i16::MAX as f32 / 4.0;
+
+// Record the screen, frame by frame, to an animated GIF or to an uncompressed Y4M video. As the display only has two
+// colors, a GIF is small and plays everywhere, but it cannot hold any sound: a Y4M video is recorded with a WAV audio
+// track next to it, which can be muxed with e.g. `ffmpeg -i clip.y4m -i clip.wav clip.mp4`.
+pub struct Recorder {
+    path: PathBuf,
+    video: Video,
+    audio: Option<Wav>,
+    scale: usize,
+    width: u16,
+    height: u16,
+
+    // Number of frames recorded so far.
+    frames: u64,
+}
+
+enum Video {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+
+        // A GIF frame is only written once the next different frame is known, to compute how long it's displayed.
+        pending: Option<Vec<u8>>,
+        time_written: u64,
+    },
+    Y4m {
+        writer: BufWriter<File>,
+
+        // The Y, Cb and Cr components of the background and foreground colors.
+        colors: [[u8; 3]; 2],
+    },
+}
+
+impl Recorder {
+    // Create a recorder whose format is given by the extension of the path: `.gif` or `.y4m`.
+    pub fn create(path: &Path, palette: &Palette, scale: u32) -> Result<Recorder, Box<dyn Error>> {
+        let scale = scale as usize;
+        let width = (SCREEN_WIDTH as usize * scale) as u16;
+        let height = (SCREEN_HEIGHT as usize * scale) as u16;
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let create = |path: &Path| {
+            File::create(path)
+                .map(BufWriter::new)
+                .map_err(|error| format!("Cannot create {}: {}", path.display(), error))
+        };
+
+        let (video, audio) = match extension.as_deref() {
+            Some("gif") => {
+                let global_palette: Vec<u8> = [palette.color(0), palette.color(1)]
+                    .iter()
+                    .flat_map(|color| color.0)
+                    .collect();
+                let mut encoder = gif::Encoder::new(create(path)?, width, height, &global_palette)?;
+
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+
+                let video = Video::Gif {
+                    encoder,
+                    pending: None,
+                    time_written: 0,
+                };
+
+                (video, None)
+            }
+            Some("y4m") => {
+                let mut writer = create(path)?;
+
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444",
+                    width, height
+                )?;
+
+                let video = Video::Y4m {
+                    writer,
+                    colors: [to_ycbcr(palette.color(0).0), to_ycbcr(palette.color(1).0)],
+                };
+                let audio = Wav::create(create(&path.with_extension("wav"))?)?;
+
+                (video, Some(audio))
+            }
+            _ => {
+                return Err(format!(
+                    "Cannot record to {}: the extension must be .gif or .y4m",
+                    path.display()
+                )
+                .into())
+            }
+        };
+
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            video,
+            audio,
+            scale,
+            width,
+            height,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Record a frame of the emulation, which lasts 1/60 s, and whether the sound was on during that frame.
+    pub fn frame(&mut self, vram: &[u8], sound: bool) -> Result<(), Box<dyn Error>> {
+        let pixels = self.scale_up(vram);
+
+        match &mut self.video {
+            Video::Gif {
+                encoder,
+                pending,
+                time_written,
+            } => {
+                if pending.as_ref() != Some(&pixels) {
+                    if let Some(previous) = pending.take() {
+                        let size = (self.width, self.height);
+
+                        write_gif_frame(encoder, size, previous, self.frames, time_written)?;
+                    }
+
+                    *pending = Some(pixels);
+                }
+            }
+            Video::Y4m { writer, colors } => {
+                writer.write_all(b"FRAME\n")?;
+
+                // The Y, Cb and Cr planes are written one after the other
+                for component in [0, 1, 2] {
+                    let plane: Vec<u8> = pixels
+                        .iter()
+                        .map(|p| colors[*p as usize][component])
+                        .collect();
+
+                    writer.write_all(&plane)?;
+                }
+            }
+        }
+
+        if let Some(audio) = &mut self.audio {
+            audio.frame(sound)?;
+        }
+
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    // Write the last frame and complete the headers.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.video {
+            Video::Gif {
+                mut encoder,
+                pending,
+                mut time_written,
+            } => {
+                if let Some(pixels) = pending {
+                    let size = (self.width, self.height);
+
+                    write_gif_frame(&mut encoder, size, pixels, self.frames, &mut time_written)?;
+                }
+
+                encoder.into_inner()?.flush()?;
+            }
+            Video::Y4m { mut writer, .. } => writer.flush()?,
+        }
+
+        if let Some(audio) = self.audio {
+            audio.finish()?;
+        }
+
+        Ok(())
+    }
+
+    // Draw every pixel of the CHIP-8 as a square of `scale` x `scale` pixels.
+    fn scale_up(&self, vram: &[u8]) -> Vec<u8> {
+        let width = SCREEN_WIDTH as usize;
+        let mut pixels = Vec::with_capacity(vram.len() * self.scale * self.scale);
+
+        for row in vram.chunks_exact(width) {
+            let scaled_row: Vec<u8> = row
+                .iter()
+                .flat_map(|p| std::iter::repeat_n(*p, self.scale))
+                .collect();
+
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&scaled_row);
+            }
+        }
+
+        pixels
+    }
+}
+
+// Write a frame displayed until the given frame of the emulation. The delays are rounded so that they don't drift from
+// the time elapsed in the emulation, and a frame displayed for less than a hundredth of a second is skipped.
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    (width, height): (u16, u16),
+    pixels: Vec<u8>,
+    until: u64,
+    time_written: &mut u64,
+) -> Result<(), gif::EncodingError> {
+    let time = until * GIF_TIME_UNITS_PER_SECOND / FRAMES_PER_SECOND;
+    let delay = time - *time_written;
+
+    if delay == 0 {
+        return Ok(());
+    }
+
+    let mut frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+
+    // The delay cannot exceed about 11 minutes, which is only reached when the screen doesn't change for that long
+    frame.delay = delay.min(u16::MAX as u64) as u16;
+    *time_written = time;
+
+    encoder.write_frame(&frame)
+}
+
+// Convert a color to the Y, Cb and Cr components used by video (ITU-R BT.601, limited range).
+fn to_ycbcr([red, green, blue]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (red as f32, green as f32, blue as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+// A mono, 16-bit PCM WAV file playing a square wave while the sound timer is active. The sizes in the header are only
+// known once the recording is finished.
+struct Wav {
+    writer: BufWriter<File>,
+    samples: u32,
+
+    // Position in the period of the square wave, from 0 to 1, kept between frames so that the wave is continuous.
+    phase: f32,
+}
+
+impl Wav {
+    fn create(mut writer: BufWriter<File>) -> io::Result<Wav> {
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?; // Size of the file minus 8 bytes
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?; // Size of the format chunk
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // Mono
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Bytes per second
+        writer.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+        writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?; // Size of the data
+
+        Ok(Wav {
+            writer,
+            samples: 0,
+            phase: 0.0,
+        })
+    }
+
+    fn frame(&mut self, sound: bool) -> io::Result<()> {
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = match (sound, self.phase < 0.5) {
+                (false, _) => 0,
+                (true, true) => BEEP_AMPLITUDE as i16,
+                (true, false) => -BEEP_AMPLITUDE as i16,
+            };
+
+            self.writer.write_all(&sample.to_le_bytes())?;
+            self.phase = (self.phase + BEEP_FREQUENCY / SAMPLE_RATE as f32).fract();
+        }
+
+        self.samples += SAMPLES_PER_FRAME as u32;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let data_size = self.samples * 2;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
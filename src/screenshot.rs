@@ -0,0 +1,163 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    cli::ScreenshotArgs,
+    config::Config,
+    cpu::Cpu,
+    database, display,
+    palette::{self, Palette},
+    persistence::{Persistence, Renderer},
+    platform,
+    settings::RomSettings,
+    DEFAULT_IPF, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+// Run the ROM without a window and without any key pressed for the given number of frames, then save the screen. The
+// emulation is deterministic, so the same screenshot is produced every time.
+pub fn run(args: ScreenshotArgs, config: Config, rom: Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let game = database::lookup(&rom)?;
+    let settings = RomSettings::load(&args.rom_path)?.or(game
+        .as_ref()
+        .map(|game| game.settings.clone())
+        .unwrap_or_default());
+    let super_chip = args.super_chip
+        || settings
+            .super_chip
+            .unwrap_or_else(|| platform::detect(&rom).super_chip());
+    let ipf = args.ipf.or(settings.ipf).unwrap_or(DEFAULT_IPF);
+    let mut cpu = Cpu::with_seed(rom, super_chip, 0)?;
+
+    for _ in 0..args.frames {
+        for _ in 0..ipf {
+            cpu.step();
+        }
+
+        cpu.decrement_timers();
+    }
+
+    let (_, palette) = palette::resolve(
+        &args.palette,
+        &config.display,
+        game.as_ref().map_or(&[], |game| &game.colors),
+    );
+    let mut renderer = Renderer::new(Persistence::None, 1.0, cpu.vram().len());
+    let path = match args.output {
+        Some(path) => path,
+        None => timestamped_path(Path::new("."), &args.rom_path, "png"),
+    };
+
+    renderer.update(cpu.vram());
+    save(renderer.brightness(), &palette, args.scale, &path)?;
+    println!("Screenshot saved to {}", path.display());
+
+    Ok(())
+}
+
+// Write the screen to a PNG file, every pixel of the CHIP-8 being drawn as a square of `scale` x `scale` pixels with
+// the colors of the palette.
+pub fn save(
+    brightness: &[f32],
+    palette: &Palette,
+    scale: u32,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let width = SCREEN_WIDTH as usize;
+    let height = SCREEN_HEIGHT as usize;
+    let scale = scale as usize;
+    let mut frame = vec![0; width * height * 4];
+
+    display::draw(brightness, &mut frame, width, palette);
+
+    let mut image = Vec::with_capacity(frame.len() * scale * scale);
+
+    for row in frame.chunks_exact(width * 4) {
+        let mut scaled_row = Vec::with_capacity(row.len() * scale);
+
+        for pixel in row.chunks_exact(4) {
+            for _ in 0..scale {
+                scaled_row.extend_from_slice(pixel);
+            }
+        }
+
+        for _ in 0..scale {
+            image.extend_from_slice(&scaled_row);
+        }
+    }
+
+    let file = File::create(path)
+        .map_err(|error| format!("Cannot create {}: {}", path.display(), error))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        (width * scale) as u32,
+        (height * scale) as u32,
+    );
+
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image)?;
+
+    Ok(())
+}
+
+// Build a path in the given directory from the name of the ROM and the current date and time (in UTC), e.g.
+// `pong-20220704-153012.png`, adding a counter if a file with the same name was already saved during that second.
+pub fn timestamped_path(directory: &Path, rom_path: &Path, extension: &str) -> PathBuf {
+    let stem = rom_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
+    let name = format!("{}-{}", stem, timestamp());
+    let mut path = directory.join(format!("{}.{}", name, extension));
+    let mut counter = 1;
+
+    while path.exists() {
+        counter += 1;
+        path = directory.join(format!("{}-{}.{}", name, counter, extension));
+    }
+
+    path
+}
+
+// Format the current time as `YYYYMMDD-hhmmss`.
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// Convert a number of days since 1970-01-01 to a date of the proleptic Gregorian calendar, with the algorithm described
+// by Howard Hinnant (http://howardhinnant.github.io/date_algorithms.html#civil_from_days). The calendar repeats itself
+// every 400 years (an era), which starts on March 1st so that the leap day is the last day of a year.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468; // Days from 0000-03-01 to 1970-01-01
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153; // From March (0) to February (11)
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::key_mapping::{self, GamepadButton};
+
+// The settings of a ROM are stored next to it, in a TOML file with the same name (e.g. `pong.toml` for `pong.ch8`).
+// Settings given on the command line take precedence over the ones found in this file.
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub super_chip: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipf: Option<u32>,
+
+    // Bindings from key names (as in `VirtualKeyCode`) to keypad values, added on top of the keyboard layout. Several
+    // keys can be bound to the same keypad value, e.g. both `W` and `Up`.
+    #[serde(
+        deserialize_with = "key_mapping::deserialize_keys",
+        serialize_with = "key_mapping::serialize_keys",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub keys: HashMap<VirtualKeyCode, u8>,
+
+    // Bindings from gamepad buttons (as in `GamepadButton`) to keypad values, replacing the default ones.
+    #[serde(
+        deserialize_with = "key_mapping::deserialize_keys",
+        serialize_with = "key_mapping::serialize_keys",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub gamepad: HashMap<GamepadButton, u8>,
+}
+
+impl RomSettings {
+    pub fn load(rom_path: &Path) -> Result<RomSettings, Box<dyn Error>> {
+        let path = settings_path(rom_path);
+
+        if !path.exists() {
+            return Ok(RomSettings::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+
+        toml::from_str(&content)
+            .map_err(|error| format!("Invalid settings in {}: {}", path.display(), error).into())
+    }
+
+    // Fill in the settings missing from these ones with other ones, e.g. the ones of the ROM in the database.
+    pub fn or(mut self, mut defaults: RomSettings) -> RomSettings {
+        defaults.keys.extend(self.keys.drain());
+
+        if !self.gamepad.is_empty() {
+            defaults.gamepad = self.gamepad;
+        }
+
+        RomSettings {
+            super_chip: self.super_chip.or(defaults.super_chip),
+            ipf: self.ipf.or(defaults.ipf),
+            keys: defaults.keys,
+            gamepad: defaults.gamepad,
+        }
+    }
+
+    pub fn save(&self, rom_path: &Path) -> Result<(), Box<dyn Error>> {
+        let path = settings_path(rom_path);
+        let content = toml::to_string(self)?;
+
+        fs::write(&path, content)
+            .map_err(|error| format!("Cannot write {}: {}", path.display(), error).into())
+    }
+}
+
+fn settings_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("toml")
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt,
+};
+
+use crate::cpu::{Cpu, RAM_SIZE};
+
+// A snapshot of the machine is taken every this many events, so that going back in time never re-executes more than
+// this number of instructions.
+const SNAPSHOT_INTERVAL: usize = 1000;
+
+// The oldest snapshots, and the events before them, are forgotten past this number, which bounds the history to about
+// half a million instructions (and the memory it uses to a few tens of megabytes).
+const MAX_SNAPSHOTS: usize = 500;
+
+// Execute a program instruction by instruction, as debuggers do. The timers are decremented once the IPF is reached, so
+// that they keep the same pace relative to the program as when it runs frame by frame.
+//
+// Every change to the machine is recorded, so that the execution can be taken back in time: the machine is restored
+// from the last snapshot before the wanted point, then the recorded events are executed again until that point. As the
+// random number generator is part of the snapshots, and the keys are part of the events, the program takes the exact
+// same path again.
+pub struct Stepper {
+    machine: Machine,
+    ipf: u32,
+
+    // The events since the oldest snapshot, and the number of them the machine is at. It's behind the last event after
+    // going back in time, and a new event then replaces the ones after it.
+    events: Vec<Event>,
+    position: usize,
+
+    // Snapshots of the machine, along with the number of events they were taken after, the oldest first.
+    snapshots: VecDeque<(usize, Machine)>,
+}
+
+// What is restored when going back in time.
+#[derive(Clone)]
+struct Machine {
+    cpu: Cpu,
+
+    // Instructions executed during the current frame, and frames executed so far.
+    instructions: u32,
+    frames: u64,
+
+    // Whether the sound timer was active during the last frame.
+    sound: bool,
+}
+
+// A register or a byte of memory whose changes stop the execution, as breakpoints do for instructions.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Watch {
+    V(u8),
+    Memory(u16),
+}
+
+// Why going back in time stopped.
+pub enum ReverseStop {
+    Breakpoint,
+
+    // The instruction about to be executed changes the watched value.
+    Watch(Watch),
+    StartOfHistory,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Event {
+    Step,
+    EndFrame,
+    PressKey(u8),
+    ReleaseKey(u8),
+}
+
+impl Stepper {
+    pub fn new(cpu: Cpu, ipf: u32) -> Stepper {
+        let machine = Machine {
+            cpu,
+            instructions: 0,
+            frames: 0,
+            sound: false,
+        };
+
+        Stepper {
+            snapshots: VecDeque::from([(0, machine.clone())]),
+            machine,
+            ipf,
+            events: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.machine.cpu
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.machine.frames
+    }
+
+    pub fn sound(&self) -> bool {
+        self.machine.sound
+    }
+
+    // Execute one instruction, and decrement the timers at the end of a frame. An instruction that would make the CPU
+    // panic, e.g. an unsupported one, isn't executed and is reported instead.
+    pub fn step(&mut self) -> Result<(), String> {
+        if let Some(fault) = self.machine.cpu.fault() {
+            return Err(fault);
+        }
+
+        self.record(Event::Step);
+
+        Ok(())
+    }
+
+    // Execute one instruction like `step`, returning the first of the watches whose value it changed, if any.
+    pub fn step_watching(&mut self, watches: &BTreeSet<Watch>) -> Result<Option<Watch>, String> {
+        let values = values(watches, &self.machine.cpu);
+
+        self.step()?;
+
+        Ok(changed(watches, values, &self.machine.cpu))
+    }
+
+    // Skip the rest of the frame, e.g. when the program is idle.
+    pub fn end_frame(&mut self) {
+        self.record(Event::EndFrame);
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.record(Event::PressKey(key));
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.record(Event::ReleaseKey(key));
+    }
+
+    // Go back to before the last instruction executed, returning `false` if the start of the history is reached.
+    pub fn step_back(&mut self) -> bool {
+        match self.events[..self.position]
+            .iter()
+            .rposition(|event| *event == Event::Step)
+        {
+            Some(position) => {
+                self.go_to(position);
+                true
+            }
+            None => {
+                self.go_to(0);
+                false
+            }
+        }
+    }
+
+    // Go back to before the last instruction executed for which `breakpoint` is true, given the CPU about to execute it,
+    // or which changed the value of one of the watches, e.g. to find which instruction last wrote a register. The machine
+    // is left at the start of the history if there is none.
+    pub fn reverse_continue(
+        &mut self,
+        breakpoint: impl Fn(&Cpu) -> bool,
+        watches: &BTreeSet<Watch>,
+    ) -> ReverseStop {
+        let end = self.position;
+
+        // The segments between two snapshots are searched from the most recent one, each being executed again to find
+        // the last instruction stopping in it
+        for index in (0..self.snapshots.len()).rev() {
+            let start = self.snapshots[index].0;
+
+            if start >= end {
+                continue;
+            }
+
+            let segment_end = match self.snapshots.get(index + 1) {
+                Some((position, _)) => (*position).min(end),
+                None => end,
+            };
+            let mut found = None;
+
+            self.machine = self.snapshots[index].1.clone();
+
+            for position in start..segment_end {
+                let event = self.events[position];
+
+                if event != Event::Step {
+                    self.machine.apply(event, self.ipf);
+                    continue;
+                }
+
+                let stopped = breakpoint(&self.machine.cpu);
+                let values = values(watches, &self.machine.cpu);
+
+                self.machine.apply(event, self.ipf);
+
+                match changed(watches, values, &self.machine.cpu) {
+                    Some(watch) => found = Some((position, ReverseStop::Watch(watch))),
+                    None if stopped => found = Some((position, ReverseStop::Breakpoint)),
+                    None => {}
+                }
+            }
+
+            if let Some((position, stop)) = found {
+                self.go_to(position);
+                return stop;
+            }
+        }
+
+        self.go_to(0);
+        ReverseStop::StartOfHistory
+    }
+
+    // Apply a new event. Going on from a point in the past forgets what happened after it.
+    fn record(&mut self, event: Event) {
+        if self.position < self.events.len() {
+            let position = self.position;
+
+            self.events.truncate(position);
+            self.snapshots.retain(|(snapshot, _)| *snapshot <= position);
+        }
+
+        self.machine.apply(event, self.ipf);
+        self.events.push(event);
+        self.position += 1;
+
+        if self.position - self.snapshots.back().map_or(0, |(position, _)| *position)
+            >= SNAPSHOT_INTERVAL
+        {
+            self.snapshots
+                .push_back((self.position, self.machine.clone()));
+        }
+
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+
+            let forgotten = self.snapshots[0].0;
+
+            self.events.drain(..forgotten);
+            self.position -= forgotten;
+
+            for (position, _) in &mut self.snapshots {
+                *position -= forgotten;
+            }
+        }
+    }
+
+    // Restore the machine as it was after the given number of events.
+    fn go_to(&mut self, position: usize) {
+        let (start, snapshot) = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(snapshot, _)| *snapshot <= position)
+            .expect("the oldest snapshot is at the start of the events");
+
+        self.machine = snapshot.clone();
+
+        for event in &self.events[*start..position] {
+            self.machine.apply(*event, self.ipf);
+        }
+
+        self.position = position;
+    }
+}
+
+impl Machine {
+    fn apply(&mut self, event: Event, ipf: u32) {
+        match event {
+            Event::Step => {
+                self.cpu.step();
+                self.instructions += 1;
+
+                if self.instructions >= ipf {
+                    self.end_frame();
+                }
+            }
+            Event::EndFrame => self.end_frame(),
+            Event::PressKey(key) => self.cpu.press_key(key),
+            Event::ReleaseKey(key) => self.cpu.release_key(key),
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.sound = self.cpu.decrement_timers();
+        self.instructions = 0;
+        self.frames += 1;
+    }
+}
+
+impl Watch {
+    // Parse a register from `V0` to `VF`, or else an address in the syntax of the debugger, e.g. a label.
+    pub fn parse(text: &str, address: impl Fn(&str) -> Option<u16>) -> Option<Watch> {
+        let text = text.trim();
+        let register = text
+            .strip_prefix(['V', 'v'])
+            .filter(|x| x.len() == 1)
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+        match register {
+            Some(x) => Some(Watch::V(x)),
+            None => address(text)
+                .filter(|address| (*address as usize) < RAM_SIZE)
+                .map(Watch::Memory),
+        }
+    }
+
+    pub fn value(&self, cpu: &Cpu) -> u8 {
+        match self {
+            Watch::V(x) => cpu.v()[*x as usize],
+            Watch::Memory(address) => cpu.ram()[*address as usize],
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::V(x) => write!(f, "V{:X}", x),
+            Watch::Memory(address) => write!(f, "0x{:03X}", address),
+        }
+    }
+}
+
+fn values(watches: &BTreeSet<Watch>, cpu: &Cpu) -> Vec<u8> {
+    watches.iter().map(|watch| watch.value(cpu)).collect()
+}
+
+fn changed(watches: &BTreeSet<Watch>, values: Vec<u8>, cpu: &Cpu) -> Option<Watch> {
+    watches
+        .iter()
+        .zip(values)
+        .find(|(watch, value)| watch.value(cpu) != *value)
+        .map(|(watch, _)| *watch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Store 5 then 6 at 0x300, then count in V1 forever. The SUPER-CHIP leaves I unchanged when storing.
+    const ROM: [u8; 14] = [
+        0x60, 0x05, // 200: LD V0, 5
+        0xA3, 0x00, // 202: LD I, 0x300
+        0xF0, 0x55, // 204: LD [I], V0
+        0x70, 0x01, // 206: ADD V0, 1
+        0xF0, 0x55, // 208: LD [I], V0
+        0x71, 0x01, // 20A: ADD V1, 1
+        0x12, 0x0A, // 20C: JP 0x20A
+    ];
+
+    fn stepper(steps: usize) -> Stepper {
+        let mut stepper = Stepper::new(Cpu::with_seed(ROM.to_vec(), true, 0).unwrap(), 10);
+
+        for _ in 0..steps {
+            stepper.step().unwrap();
+        }
+
+        stepper
+    }
+
+    #[test]
+    fn step_back() {
+        let mut stepper = stepper(50);
+        let (pc, v) = (stepper.cpu().pc(), *stepper.cpu().v());
+
+        for _ in 0..10 {
+            stepper.step().unwrap();
+        }
+
+        stepper.press_key(3);
+
+        for _ in 0..10 {
+            assert!(stepper.step_back());
+        }
+
+        assert_eq!((stepper.cpu().pc(), *stepper.cpu().v()), (pc, v));
+        assert_eq!(stepper.frames(), 5);
+
+        // Going on from the past forgets the future
+        stepper.step().unwrap();
+        assert_eq!(stepper.events.len(), 51);
+    }
+
+    #[test]
+    fn step_back_to_start() {
+        let mut stepper = stepper(2);
+
+        assert!(stepper.step_back());
+        assert!(stepper.step_back());
+        assert!(!stepper.step_back());
+        assert_eq!(stepper.cpu().pc(), 0x200);
+    }
+
+    #[test]
+    fn same_random_numbers() {
+        let rom = vec![0xC0, 0xFF, 0x12, 0x00]; // RND V0, 0xFF then JP 0x200
+        let mut stepper = Stepper::new(Cpu::with_seed(rom, false, 1).unwrap(), 10);
+        let run = |stepper: &mut Stepper| {
+            (0..20)
+                .map(|_| {
+                    stepper.step().unwrap();
+                    stepper.cpu().v()[0]
+                })
+                .collect::<Vec<u8>>()
+        };
+
+        let first = run(&mut stepper);
+
+        while stepper.step_back() {}
+
+        assert_eq!(run(&mut stepper), first);
+    }
+
+    #[test]
+    fn reverse_continue_to_breakpoint() {
+        let mut stepper = stepper(30);
+        let stop = stepper.reverse_continue(|cpu| cpu.pc() == 0x206, &BTreeSet::new());
+
+        assert!(matches!(stop, ReverseStop::Breakpoint));
+        assert_eq!(stepper.cpu().pc(), 0x206);
+        assert_eq!(stepper.cpu().v()[0], 5);
+
+        let stop = stepper.reverse_continue(|cpu| cpu.pc() == 0x206, &BTreeSet::new());
+
+        assert!(matches!(stop, ReverseStop::StartOfHistory));
+        assert_eq!(stepper.cpu().pc(), 0x200);
+    }
+
+    #[test]
+    fn reverse_continue_to_watch() {
+        let mut stepper = stepper(30);
+        let watches = BTreeSet::from([Watch::Memory(0x300)]);
+
+        // The last instruction that wrote the byte, then the one before
+        let stop = stepper.reverse_continue(|_| false, &watches);
+
+        assert!(matches!(stop, ReverseStop::Watch(Watch::Memory(0x300))));
+        assert_eq!(stepper.cpu().pc(), 0x208);
+        assert_eq!(stepper.cpu().ram()[0x300], 5);
+
+        let stop = stepper.reverse_continue(|_| false, &watches);
+
+        assert!(matches!(stop, ReverseStop::Watch(Watch::Memory(0x300))));
+        assert_eq!(stepper.cpu().pc(), 0x204);
+        assert_eq!(stepper.cpu().ram()[0x300], 0);
+
+        let stop = stepper.reverse_continue(|_| false, &watches);
+
+        assert!(matches!(stop, ReverseStop::StartOfHistory));
+    }
+
+    #[test]
+    fn watch_preferred_to_breakpoint() {
+        let mut stepper = stepper(30);
+        let watches = BTreeSet::from([Watch::V(0)]);
+        let stop = stepper.reverse_continue(|cpu| cpu.pc() == 0x206, &watches);
+
+        assert!(matches!(stop, ReverseStop::Watch(Watch::V(0))));
+        assert_eq!(stepper.cpu().pc(), 0x206);
+    }
+
+    #[test]
+    fn step_watching() {
+        let mut stepper = stepper(2);
+        let watches = BTreeSet::from([Watch::Memory(0x300), Watch::V(1)]);
+
+        assert_eq!(
+            stepper.step_watching(&watches),
+            Ok(Some(Watch::Memory(0x300)))
+        );
+        assert_eq!(stepper.step_watching(&watches), Ok(None));
+        assert_eq!(
+            stepper.step_watching(&watches),
+            Ok(Some(Watch::Memory(0x300)))
+        );
+        assert_eq!(stepper.step_watching(&watches), Ok(Some(Watch::V(1))));
+    }
+
+    #[test]
+    fn fault() {
+        let mut stepper = Stepper::new(Cpu::with_seed(vec![0x00, 0xEE], false, 0).unwrap(), 10);
+
+        assert!(stepper.step().is_err());
+        assert!(stepper.events.is_empty());
+    }
+
+    #[test]
+    fn forget_oldest_events() {
+        let steps = SNAPSHOT_INTERVAL * (MAX_SNAPSHOTS + 10);
+        let mut stepper = stepper(steps);
+
+        assert_eq!(stepper.snapshots.len(), MAX_SNAPSHOTS);
+        assert!(stepper.events.len() <= SNAPSHOT_INTERVAL * MAX_SNAPSHOTS);
+        assert_eq!(stepper.snapshots[0].0, 0);
+
+        // V1 is incremented every other instruction
+        let v1 = stepper.cpu().v()[1];
+
+        assert!(stepper.step_back());
+        assert!(stepper.step_back());
+        assert_eq!(stepper.cpu().v()[1], v1.wrapping_sub(1));
+
+        // The start of the program is forgotten
+        let stop = stepper.reverse_continue(|cpu| cpu.pc() == 0x200, &BTreeSet::new());
+
+        assert!(matches!(stop, ReverseStop::StartOfHistory));
+        assert_eq!(stepper.cpu().pc(), stepper.snapshots[0].1.cpu.pc());
+        assert!(stepper.frames() > 0);
+    }
+
+    #[test]
+    fn parse_watch() {
+        let address = |text: &str| match text {
+            "counter" => Some(0x300),
+            _ => u16::from_str_radix(text, 16).ok(),
+        };
+
+        assert_eq!(Watch::parse("vA", address), Some(Watch::V(0xA)));
+        assert_eq!(
+            Watch::parse(" counter ", address),
+            Some(Watch::Memory(0x300))
+        );
+        assert_eq!(Watch::parse("FFF", address), Some(Watch::Memory(0xFFF)));
+        assert_eq!(Watch::parse("1000", address), None);
+        assert_eq!(Watch::parse("VG", address), None);
+        assert_eq!(Watch::Memory(0x2A).to_string(), "0x02A");
+    }
+}
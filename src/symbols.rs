@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::operation::Operation;
+
+// The labels and the source lines of a program, written by the assembler next to the ROM (e.g. `pong.sym` for
+// `pong.ch8`) so that the debuggers and the profiler can show `main_loop+4` instead of `0x2A6`.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Symbols {
+    // Path of the source file, relative to the directory of the symbol file unless it's absolute.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+
+    // Address and source line (from 1) of every instruction and every line of data, sorted by address.
+    pub lines: Vec<(u16, u32)>,
+
+    // Address of every label.
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Symbols {
+    // Load the symbols written next to a ROM, if any.
+    pub fn load(rom_path: &Path) -> Result<Symbols, Box<dyn Error>> {
+        let path = symbols_path(rom_path);
+
+        if !path.exists() {
+            return Ok(Symbols::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+        let mut symbols: Symbols = toml::from_str(&content)
+            .map_err(|error| format!("Invalid symbols in {}: {}", path.display(), error))?;
+
+        // The source is found relative to the symbol file
+        if let (Some(source), Some(directory)) = (&symbols.source, path.parent()) {
+            symbols.source = Some(directory.join(source));
+        }
+
+        symbols.lines.sort_unstable();
+
+        Ok(symbols)
+    }
+
+    pub fn save(&self, rom_path: &Path) -> Result<(), Box<dyn Error>> {
+        let path = symbols_path(rom_path);
+        let content = toml::to_string(self)?;
+
+        fs::write(&path, content)
+            .map_err(|error| format!("Cannot write {}: {}", path.display(), error).into())
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, label_address)| **label_address == address)
+            .map(|(label, _)| label.as_str())
+    }
+
+    // Describe an address relative to the closest label before it (e.g. `main_loop+4`), or in hexadecimal if there is
+    // no label before it.
+    pub fn describe(&self, address: u16) -> String {
+        let label = self
+            .labels
+            .iter()
+            .filter(|(_, label_address)| **label_address <= address)
+            .max_by_key(|(_, label_address)| **label_address);
+
+        match label {
+            Some((label, label_address)) if *label_address == address => label.clone(),
+            Some((label, label_address)) => format!("{}+{}", label, address - label_address),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    // Describe an address along with its value in hexadecimal, e.g. `main_loop+4 (0x2A6)`.
+    pub fn annotate(&self, address: u16) -> String {
+        match self.describe(address) {
+            name if name.starts_with("0x") => name,
+            name => format!("{} (0x{:03X})", name, address),
+        }
+    }
+
+    // Disassemble an operation, showing the label of its target address if there is one.
+    pub fn disassemble(&self, operation: Operation) -> String {
+        let target = match operation {
+            Operation::JumpTo { address }
+            | Operation::CallSubroutineAt { address }
+            | Operation::SetITo { address } => self.label_at(address),
+            _ => None,
+        };
+
+        match (operation, target) {
+            (Operation::JumpTo { .. }, Some(label)) => format!("JP {}", label),
+            (Operation::CallSubroutineAt { .. }, Some(label)) => format!("CALL {}", label),
+            (Operation::SetITo { .. }, Some(label)) => format!("LD I, {}", label),
+            _ => operation.to_string(),
+        }
+    }
+
+    // The source line of the instruction at the given address.
+    pub fn line_at(&self, address: u16) -> Option<u32> {
+        self.lines
+            .binary_search_by_key(&address, |(line_address, _)| *line_address)
+            .ok()
+            .map(|index| self.lines[index].1)
+    }
+
+    // The address of the first instruction on the given source line, or on the next line with one (e.g. for a
+    // breakpoint set on a comment), along with its line.
+    pub fn address_at_line(&self, line: u32) -> Option<(u16, u32)> {
+        self.lines
+            .iter()
+            .filter(|(_, address_line)| *address_line >= line)
+            .min_by_key(|(address, address_line)| (*address_line, *address))
+            .copied()
+    }
+}
+
+fn symbols_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sym")
+}
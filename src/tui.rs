@@ -0,0 +1,515 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, Stdout, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    queue,
+    style::{self, Color, Print, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, ClearType},
+};
+use winit::event::{ElementState, VirtualKeyCode};
+
+use crate::{
+    cheats::Cheat,
+    cpu::Cpu,
+    emulator::{self, Input, Options, Output},
+    key_mapping::{HostKey, KeyMapping, Keypad},
+    palette::Palette,
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+// Most terminals only report key presses, repeated while the key is held. A key is released when it hasn't been
+// repeated for this long, which is a bit longer than the usual delay before the first repetition.
+const KEY_TIMEOUT: Duration = Duration::from_millis(600);
+
+// The terminal is polled for key events at this interval, and redrawn at most once per poll.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// The bell is rung when the sound starts, not at every frame while it plays.
+const BELL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long the answer to a cheat hotkey stays in the status bar.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Glyphs {
+    /// Two pixels per character with the upper half block, in color (64x16 characters)
+    HalfBlocks,
+
+    /// Eight pixels per character with the braille patterns (32x8 characters)
+    Braille,
+}
+
+// The state of the terminal is restored when this guard is dropped, even when leaving because of an error.
+pub struct RawTerminal {
+    pub stdout: Stdout,
+    keyboard_enhanced: bool,
+}
+
+impl RawTerminal {
+    pub fn enter() -> io::Result<RawTerminal> {
+        let mut stdout = io::stdout();
+
+        terminal::enable_raw_mode()?;
+
+        // Ask the terminal to report the key releases, if it supports the keyboard enhancement protocol
+        let keyboard_enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+
+        if keyboard_enhanced {
+            queue!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        queue!(
+            stdout,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(ClearType::All)
+        )?;
+        stdout.flush()?;
+
+        Ok(RawTerminal {
+            stdout,
+            keyboard_enhanced,
+        })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if self.keyboard_enhanced {
+            let _ = queue!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+
+        let _ = queue!(
+            self.stdout,
+            style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = self.stdout.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Keys pressed in the terminal. Most terminals only report key presses, so the releases are emulated with a timeout
+// until the terminal reports one.
+pub struct TerminalKeys {
+    // The time at which every held key is released, unless it's repeated before.
+    deadlines: HashMap<HostKey, Instant>,
+    releases_reported: bool,
+}
+
+impl TerminalKeys {
+    pub fn new(terminal: &RawTerminal) -> TerminalKeys {
+        TerminalKeys {
+            deadlines: HashMap::new(),
+            releases_reported: terminal.keyboard_enhanced,
+        }
+    }
+
+    // Convert a key event of the terminal to the key of the window it comes from, and whether it's pressed or released.
+    pub fn key_event(
+        &mut self,
+        code: KeyCode,
+        kind: KeyEventKind,
+    ) -> Option<(HostKey, VirtualKeyCode, ElementState)> {
+        let code = virtual_keycode(code)?;
+        let source = HostKey::Virtual(code);
+        let state = match kind {
+            KeyEventKind::Release => {
+                self.releases_reported = true;
+                self.deadlines.clear();
+                ElementState::Released
+            }
+            _ => {
+                if !self.releases_reported {
+                    self.deadlines.insert(source, Instant::now() + KEY_TIMEOUT);
+                }
+
+                ElementState::Pressed
+            }
+        };
+
+        Some((source, code, state))
+    }
+
+    // The keys that haven't been repeated for too long, which are considered released.
+    pub fn expired(&mut self) -> Vec<HostKey> {
+        let now = Instant::now();
+        let expired = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| now >= **deadline)
+            .map(|(source, _)| *source)
+            .collect();
+
+        self.deadlines.retain(|_, deadline| now < *deadline);
+
+        expired
+    }
+}
+
+// Run a ROM in the terminal, e.g. over SSH, with the same emulator thread as the window. The keys are mapped like in
+// the window, from the characters reported by the terminal.
+pub fn run(
+    cpu: Cpu,
+    options: Options,
+    key_mapping: KeyMapping,
+    palette: Palette,
+    glyphs: Glyphs,
+    title: String,
+) -> Result<(), Box<dyn Error>> {
+    let mut terminal = RawTerminal::enter()?;
+    let (inputs, receiver) = mpsc::channel();
+    let (sender, outputs) = mpsc::channel();
+    let ipf = options.ipf;
+    let emulator = emulator::spawn(cpu, options, receiver, move |output| {
+        sender.send(output).is_ok()
+    });
+    let mut screen = Screen {
+        vram: vec![0; SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize],
+        palette,
+        glyphs,
+        title,
+        ipf,
+        speed: 1.0,
+        waiting: false,
+        message: None,
+    };
+    let result = event_loop(&mut terminal, &mut screen, &key_mapping, &inputs, &outputs);
+
+    let _ = inputs.send(Input::Quit);
+    let _ = emulator.join();
+
+    result
+}
+
+// What is drawn in the terminal: the screen of the CHIP-8 and a status bar below it.
+struct Screen {
+    vram: Vec<u8>,
+    palette: Palette,
+    glyphs: Glyphs,
+    title: String,
+    ipf: u32,
+
+    // Speed of the emulation compared to the original hardware, and whether the program waits for a key.
+    speed: f32,
+    waiting: bool,
+
+    // The answer to the last cheat hotkey, replacing the help of the status bar until it expires.
+    message: Option<(String, Instant)>,
+}
+
+fn event_loop(
+    terminal: &mut RawTerminal,
+    screen: &mut Screen,
+    key_mapping: &KeyMapping,
+    inputs: &Sender<Input>,
+    outputs: &Receiver<Output>,
+) -> Result<(), Box<dyn Error>> {
+    let mut keypad = Keypad::default();
+    let mut keys = TerminalKeys::new(terminal);
+    let mut last_bell = None;
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            draw(&mut terminal.stdout, screen, &keypad)?;
+            redraw = false;
+        }
+
+        if event::poll(POLL_INTERVAL)? {
+            match event::read()? {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) => return Ok(()),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers,
+                    ..
+                }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                // F4 to F8 search the memory and freeze the bytes found, like in the window
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(number),
+                    modifiers,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    if let Some(cheat) =
+                        Cheat::from_key(number, modifiers.contains(KeyModifiers::SHIFT))
+                    {
+                        let _ = inputs.send(Input::Cheat(cheat));
+                    }
+                }
+                Event::Key(KeyEvent { code, kind, .. }) => {
+                    let (source, code, state) = match keys.key_event(code, kind) {
+                        Some(key) => key,
+                        None => continue,
+                    };
+
+                    redraw |= crate::send_key(
+                        inputs,
+                        &mut keypad,
+                        source,
+                        key_mapping.key(0, Some(code)),
+                        state,
+                    );
+                }
+                Event::Resize(..) => {
+                    queue!(terminal.stdout, terminal::Clear(ClearType::All))?;
+                    redraw = true;
+                }
+                _ => {}
+            }
+        }
+
+        for source in keys.expired() {
+            redraw |= crate::send_key(inputs, &mut keypad, source, None, ElementState::Released);
+        }
+
+        let now = Instant::now();
+
+        if screen
+            .message
+            .as_ref()
+            .is_some_and(|(_, deadline)| now >= *deadline)
+        {
+            screen.message = None;
+            redraw = true;
+        }
+
+        for output in outputs.try_iter() {
+            match output {
+                Output::Frame(frame) => {
+                    screen.vram = frame;
+                    redraw = true;
+                }
+                Output::Beep => {
+                    if last_bell.is_none_or(|bell: Instant| now - bell >= BELL_INTERVAL) {
+                        queue!(terminal.stdout, Print('\x07'))?;
+                    }
+
+                    last_bell = Some(now);
+                }
+                Output::KeypadUsage { waiting, .. } => {
+                    screen.waiting = waiting;
+                    redraw = true;
+                }
+                Output::Stats {
+                    frames_per_second, ..
+                } => {
+                    screen.speed = frames_per_second / 60.0;
+                    redraw = true;
+                }
+                // The details would be drawn over the screen, so only the message is shown
+                Output::Message { text, .. } => {
+                    screen.message = Some((text, now + MESSAGE_DURATION));
+                    redraw = true;
+                }
+            }
+        }
+    }
+}
+
+fn draw(stdout: &mut Stdout, screen: &Screen, keypad: &Keypad) -> io::Result<()> {
+    let background = color(&screen.palette, 0);
+    let foreground = color(&screen.palette, 1);
+    let width = SCREEN_WIDTH as usize;
+    let rows: Vec<&[u8]> = screen.vram.chunks_exact(width).collect();
+
+    queue!(stdout, cursor::MoveTo(0, 0))?;
+
+    match screen.glyphs {
+        // The upper half of the character takes the foreground color and the lower half the background color, so
+        // every character shows two pixels on top of each other.
+        Glyphs::HalfBlocks => {
+            // The colors are only changed when needed, to keep the output small over slow connections
+            let mut colors = None;
+
+            for (line, pair) in rows.chunks_exact(2).enumerate() {
+                queue!(stdout, cursor::MoveTo(0, line as u16))?;
+
+                for pixels in pair[0].iter().zip(pair[1]) {
+                    if colors != Some(pixels) {
+                        let (top, bottom) = pixels;
+
+                        queue!(
+                            stdout,
+                            SetForegroundColor(if *top == 1 { foreground } else { background }),
+                            SetBackgroundColor(if *bottom == 1 { foreground } else { background })
+                        )?;
+                        colors = Some(pixels);
+                    }
+
+                    queue!(stdout, Print('▀'))?;
+                }
+            }
+        }
+
+        // Every character shows 2x4 pixels
+        Glyphs::Braille => {
+            queue!(
+                stdout,
+                SetForegroundColor(foreground),
+                SetBackgroundColor(background)
+            )?;
+
+            for (line, text) in braille_lines(&screen.vram).into_iter().enumerate() {
+                queue!(stdout, cursor::MoveTo(0, line as u16), Print(text))?;
+            }
+        }
+    }
+
+    let screen_lines = match screen.glyphs {
+        Glyphs::HalfBlocks => SCREEN_HEIGHT / 2,
+        Glyphs::Braille => SCREEN_HEIGHT / 4,
+    };
+    let held: String = (0..16u8)
+        .filter(|key| keypad.is_held(*key))
+        .map(|key| format!("{:X}", key))
+        .collect();
+    let status = format!(
+        " {} | {} IPF | {:.0}% | keys: {}{} | {}",
+        screen.title,
+        screen.ipf,
+        screen.speed * 100.0,
+        if held.is_empty() { "-" } else { &held },
+        if screen.waiting { " (waiting)" } else { "" },
+        screen
+            .message
+            .as_ref()
+            .map_or("Esc: quit", |(text, _)| text)
+    );
+
+    queue!(
+        stdout,
+        style::ResetColor,
+        cursor::MoveTo(0, screen_lines as u16),
+        terminal::Clear(ClearType::CurrentLine),
+        style::SetAttribute(style::Attribute::Reverse),
+        Print(status),
+        style::SetAttribute(style::Attribute::Reset)
+    )?;
+
+    stdout.flush()
+}
+
+// Draw the screen with the braille patterns. Every pattern is a grid of 2x4 dots, each of them being one bit of the code
+// point (the dots of the last row were added last, hence the unusual order).
+pub fn braille_lines(vram: &[u8]) -> Vec<String> {
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let width = SCREEN_WIDTH as usize;
+    let rows: Vec<&[u8]> = vram.chunks_exact(width).collect();
+
+    rows.chunks_exact(4)
+        .map(|block| {
+            (0..width / 2)
+                .map(|column| {
+                    let mut pattern = 0;
+
+                    for (row, dots) in block.iter().zip(DOTS) {
+                        for (dx, dot) in dots.iter().enumerate() {
+                            if row[column * 2 + dx] == 1 {
+                                pattern |= dot;
+                            }
+                        }
+                    }
+
+                    char::from_u32(0x2800 + pattern).unwrap_or(' ')
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn color(palette: &Palette, pixel: u8) -> Color {
+    let [r, g, b] = palette.color(pixel).0;
+
+    Color::Rgb { r, g, b }
+}
+
+// Terminals report characters rather than keys. They're converted to the key codes of the window so that the same
+// layouts and bindings can be used.
+fn virtual_keycode(code: KeyCode) -> Option<VirtualKeyCode> {
+    let code = match code {
+        KeyCode::Up => VirtualKeyCode::Up,
+        KeyCode::Down => VirtualKeyCode::Down,
+        KeyCode::Left => VirtualKeyCode::Left,
+        KeyCode::Right => VirtualKeyCode::Right,
+        KeyCode::Enter => VirtualKeyCode::Return,
+        KeyCode::Tab => VirtualKeyCode::Tab,
+        KeyCode::Backspace => VirtualKeyCode::Back,
+        KeyCode::Char(c) => match c.to_ascii_lowercase() {
+            ' ' => VirtualKeyCode::Space,
+            '\'' => VirtualKeyCode::Apostrophe,
+            ',' => VirtualKeyCode::Comma,
+            '.' => VirtualKeyCode::Period,
+            ';' => VirtualKeyCode::Semicolon,
+            '/' => VirtualKeyCode::Slash,
+            '-' => VirtualKeyCode::Minus,
+            '=' => VirtualKeyCode::Equals,
+            c @ '0'..='9' => DIGITS[c as usize - '0' as usize],
+            c @ 'a'..='z' => LETTERS[c as usize - 'a' as usize],
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+const DIGITS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
+
+const LETTERS: [VirtualKeyCode; 26] = [
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+];